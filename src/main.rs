@@ -1,14 +1,16 @@
-use std::collections::HashSet;
-use std::collections::HashMap;
+use std::collections::{HashMap,BTreeMap};
+use std::any::{Any,TypeId};
 use std::collections::VecDeque;
-use chrono::{NaiveDateTime};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash,Hasher};
+use chrono::{NaiveDateTime,NaiveDate,Duration,Datelike,Timelike};
 
 use std::env;
 use std::fs::File;
 
 use petgraph::{Graph,Directed};
-use petgraph::graph::{Edges,EdgeReference};
-use petgraph::prelude::{NodeIndex,EdgeIndex};
+use petgraph::graph::{EdgeReference};
+use petgraph::prelude::NodeIndex;
 use petgraph::visit::EdgeRef;
 
 use serde::{Serialize,Deserialize};
@@ -18,211 +20,1265 @@ use std::io::Read;
 use csv::{Reader,Writer};
 use std::iter;
 
-use std::rc::{Rc};
+use std::sync::{Arc,OnceLock};
+use std::cell::{Cell,RefCell};
 use std::cmp::Ordering;
+use std::sync::atomic::{AtomicU32,AtomicUsize,Ordering as AtomicOrdering};
+use std::thread;
 use std::fs;
 use std::str::FromStr;
 
+//The value types a column can hold, each as its variant name and cell type. Column, ColumnPair, ColumnPairMut and
+//the dispatch macros below are all generated from this list, so a new type only also needs a ColumnSchema and casts.
+macro_rules! column_types {
+    ($callback:ident!{$($args:tt)*})=>{
+        $callback!{[String:Str,Numeric:f64,Time:NaiveDateTime,Integer:i64,Boolean:bool,Decimal:Decimal] $($args)*}
+    }
+}
+//evaluates body with v bound to the column's vector, whatever its type. Bodies clone cells, which some types only copy.
+macro_rules! with_column {
+    ([$($name:ident:$t:ty),*] @arms $column:expr,$v:ident,$body:expr)=>{match $column {$(#[allow(clippy::clone_on_copy)] Column::$name($v)=>$body),*}};
+    ($column:expr,$v:ident=>$body:expr)=>{column_types!{with_column!{@arms $column,$v,$body}}};
+}
+//a column of the same type as the given one, holding the vector body builds from it
+macro_rules! map_same {
+    ([$($name:ident:$t:ty),*] @arms $column:expr,$v:ident,$body:expr)=>{match $column {$(#[allow(clippy::clone_on_copy)] Column::$name($v)=>Column::$name($body)),*}};
+    ($column:expr,$v:ident=>$body:expr)=>{column_types!{map_same!{@arms $column,$v,$body}}};
+}
+//evaluates body with a and b bound to the two vectors; mixed numeric types are compared as widened vectors
+//unless a separate body is given for them
+macro_rules! with_pair {
+    ([$($name:ident:$t:ty),*] @arms $pair:expr,$a:ident,$b:ident,$body:expr,$x:ident,$y:ident,$widened:expr)=>{
+        match $pair {$(#[allow(clippy::clone_on_copy)] ColumnPair::$name($a,$b)=>$body,)* ColumnPair::Widened($x,$y)=>$widened}
+    };
+    ($pair:expr,$a:ident,$b:ident=>$body:expr; widened $x:ident,$y:ident=>$widened:expr)=>{
+        column_types!{with_pair!{@arms $pair,$a,$b,$body,$x,$y,$widened}}
+    };
+    ($pair:expr,$a:ident,$b:ident=>$body:expr)=>{
+        with_pair!($pair,$a,$b=>$body; widened x,y=>{let (x,y) = (x.widened(),y.widened()); let ($a,$b) = (&x,&y); $body})
+    };
+}
+macro_rules! with_pair_mut {
+    ([$($name:ident:$t:ty),*] @arms $pair:expr,$a:ident,$b:ident,$body:expr)=>{match $pair {$(#[allow(clippy::clone_on_copy)] ColumnPairMut::$name($a,$b)=>$body),*}};
+    ($pair:expr,$a:ident,$b:ident=>$body:expr)=>{column_types!{with_pair_mut!{@arms $pair,$a,$b,$body}}};
+}
+macro_rules! define_columns {
+    ([$($name:ident:$t:ty),*])=>{
+        #[derive(Debug,Clone)]
+        enum Column {$($name(Vec<Option<$t>>)),*}
+        impl Column {
+            fn type_name(&self)->&'static str {
+                match self {$(Column::$name(_)=>stringify!($name)),*}
+            }
+        }
+        enum ColumnPair<'a> {
+            $($name(&'a Vec<Option<$t>>,&'a Vec<Option<$t>>),)*
+            Widened(NumericRef<'a>,NumericRef<'a>)//two different numeric types
+        }
+        fn columns_same_type<'a>(a:&'a Column,b:&'a Column)->Option<ColumnPair<'a>> {
+            match (a,b) {
+                $((Column::$name(a),Column::$name(b))=>Some(ColumnPair::$name(a,b)),)*
+                (a,b)=>Some(ColumnPair::Widened(NumericRef::of(a)?,NumericRef::of(b)?))
+            }
+        }
+        //assignment needs the exact same type; see widen_columns
+        enum ColumnPairMut<'a> {$($name(&'a mut Vec<Option<$t>>,&'a Vec<Option<$t>>)),*}
+        fn columns_same_type_mut<'a>(a:&'a mut Column,b:&'a Column)->Option<ColumnPairMut<'a>> {
+            match (a,b) {
+                $((Column::$name(a),Column::$name(b))=>Some(ColumnPairMut::$name(a,b)),)*
+                _=>None
+            }
+        }
+    }
+}
+
 type Tname = usize;
 type Cname = usize;
 
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
 enum Key {
-    Asc(Cname),
-    Desc(Cname)
+    Asc(Cname,Nulls),
+    Desc(Cname,Nulls)
 }
-enum Col {
-    Named(Cname),
-    GC(Box<Gc>)
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+enum Nulls {
+    First,
+    Last
+}
+impl Key {
+    fn column(&self)->Cname {
+        match self {Key::Asc(c,_)|Key::Desc(c,_)=>*c}
+    }
+    //NULL sorts as larger than any value unless the key says otherwise, as in Postgres
+    fn asc(c:Cname)->Key {Key::Asc(c,Nulls::Last)}
+    fn desc(c:Cname)->Key {Key::Desc(c,Nulls::First)}
+    fn nulls_default(&self)->bool {
+        matches!(self,Key::Asc(_,Nulls::Last)|Key::Desc(_,Nulls::First))
+    }
+    //the order of two rows under this key alone; NULLs tie with each other and go wherever the key puts them
+    fn compare(&self,t:&Table,x:usize,y:usize)->Ordering {
+        self.comparator(t)(x,y)
+    }
+    //compare for many pairs of rows of one table
+    fn comparator<'a>(&self,t:&'a Table)->impl Fn(usize,usize)->Ordering+'a {
+        let c = self.column();
+        let cells = CellCompare::new(&t.columns[c],&t.columns[c],&EXACT);
+        let null:Vec<bool> = (0..t.rows).map(|r|value_is_null(t,c,r)).collect();
+        let (first,asc) = (matches!(self,Key::Asc(_,Nulls::First)|Key::Desc(_,Nulls::First)),matches!(self,Key::Asc(..)));
+        move |x,y|match (null[x],null[y]) {
+            (true,true)=>Ordering::Equal,
+            (true,false)=>if first {Ordering::Less} else {Ordering::Greater},
+            (false,true)=>if first {Ordering::Greater} else {Ordering::Less},
+            (false,false)=>if asc {cells.ordering(x,y)} else {cells.ordering(x,y).reverse()}
+        }
+    }
+    fn sql(&self,columns:&[(String,String)])->String {
+        let (c,nulls) = match self {Key::Asc(c,n)|Key::Desc(c,n)=>(c,n)};
+        let dir = if matches!(self,Key::Asc(..)) {"ASC"} else {"DESC"};
+        if self.nulls_default() {format!("{} {}",columns[*c].0,dir)}
+        else {format!("{} {} NULLS {}",columns[*c].0,dir,if *nulls==Nulls::First {"FIRST"} else {"LAST"})}
+    }
 }
 type Gc = (Agg,Cname);
 type Pairs = Vec<Pair>;
 type Pair = (Cname,Cname);
 type Pred = Vec<Prim>;
+type Proj = (Expr,Option<String>);//an output column, with the name it's given if that differs from its source's
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
 enum Prim {
-    Const(Col,Binop),
+    Cmp(Expr,Binop,Expr),
+    Like(Expr,String),//% matches any run of characters, _ any single one
     IsNull(Cname),
-    IsNotNull(Cname)
+    IsNotNull(Cname),
+    //subqueries are evaluated on their own; the pairs (outer column, subquery column) restrict them to matching rows
+    #[allow(dead_code)]//not searched for
+    In(Expr,Box<Tier1Table>,Pairs),
+    #[allow(dead_code)]
+    NotIn(Expr,Box<Tier1Table>,Pairs),
+    Exists(Box<Tier1Table>,Pairs),
+    NotExists(Box<Tier1Table>,Pairs),
+    CmpSubquery(Expr,Binop,Box<Tier1Table>,Pairs)//against the first column of the single matching row
+}
+//f64 constant that can live in a hashed program
+#[derive(Debug,Clone,Copy)]
+struct Num(f64);
+impl PartialEq for Num {
+    fn eq(&self,other:&Self)->bool {self.0.to_bits()==other.0.to_bits()}
 }
+impl Eq for Num {}
+impl Hash for Num {
+    fn hash<H:Hasher>(&self,state:&mut H) {self.0.to_bits().hash(state)}
+}
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+enum Arith {
+    Add,
+    Sub,
+    Mul,
+    Div
+}
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+enum Expr {
+    Col(Cname),
+    Num(Num),
+    Neg(Box<Expr>),
+    Arith(Arith,Box<Expr>,Box<Expr>),
+    Round(Box<Expr>,i32),
+    Cast(Box<Expr>,ColumnSchema),
+    Str(String),
+    Bool(bool),
+    Apply(StringFn,Box<Expr>),
+    Substr(Box<Expr>,usize,Option<usize>),//1-based start, length
+    Concat(Vec<Expr>),
+    #[allow(dead_code)]//not searched for
+    Replace(Box<Expr>,String,String),
+    Time(NaiveDateTime),
+    Extract(DatePart,Box<Expr>),
+    Trunc(DatePart,Box<Expr>),
+    DateDiff(Box<Expr>,Box<Expr>),//in days
+    Interval(Box<Expr>,i64,DatePart),
+    Case(Vec<(Pred,Expr)>,Box<Expr>)//the first branch whose condition holds, else the last expression
+}
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+enum DatePart {
+    Year,
+    Month,
+    Day,
+    Hour
+}
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+enum StringFn {
+    Upper,
+    Lower,
+    Trim,
+    Length
+}
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
 enum Agg {
     Max,
     Min,
+    Count,
+    Sum,
+    Avg
+}
+impl Agg {
+    //max and min keep the whole row holding the extreme value; the others add a column
+    fn in_place(&self)->bool {
+        matches!(self,Agg::Max|Agg::Min)
+    }
+    fn sql(&self)->&'static str {
+        match self {Agg::Max=>"MAX",Agg::Min=>"MIN",Agg::Count=>"COUNT",Agg::Sum=>"SUM",Agg::Avg=>"AVG"}
+    }
 }
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
 enum Binop {
     Eq,
     Lt,
     Lteq,
     Gt,
     Gteq,
+    #[allow(dead_code)]//not searched for
     Neq
 }
 
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
 enum Tier1Table {
     Order(Tier2Table,Vec<Key>),
+    Limit(Tier2Table,Vec<Key>,usize,usize),//ordering, count, offset
+    Set(SetOp,bool,Box<Tier1Table>,Box<Tier1Table>),//ALL keeps duplicates
     N(Tier2Table)
 }
-use Tier1Table::{*};
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+enum SetOp {
+    Union,
+    Intersect,
+    Except
+}
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
 enum Tier2Table {
-    Project(Tier3Table,Vec<Cname>),
+    Project(Tier3Table,Vec<Proj>),
+    Distinct(Tier3Table,Vec<Proj>),//projection without duplicate rows
     N(Tier3Table)
 }
-use Tier2Table::{*};
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
 enum Tier3Table {
     Select(Tier4Table,Pred),
     N(Tier4Table)
 }
-use Tier3Table::{*};
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
 enum Tier4Table {
     Named(Tname),
-    Group( Box<Tier3Table>, Vec<Cname>, Vec<Gc>),
+    Group( Box<Tier3Table>, Vec<Expr>, Vec<Gc>),//keys that aren't plain columns are appended, then the added aggregates
     Join( Box<Tier4Table>, Box<Tier4Table>, Pairs),
-    LeftJoin( Box<Tier4Table>, Box<Tier3Table>, Pair)
+    #[allow(dead_code)]//not searched for
+    LeftJoin( Box<Tier4Table>, Box<Tier3Table>, Pair),
+    Window( Box<Tier3Table>, Vec<Cname>, Vec<Key>, WindowFn),//partition, order, and the function's column appended
+    Unpivot( Box<Tier3Table>, Vec<(Cname,String)>),//columns of one type, with the labels they become; the others are kept, then the label and the value appended
+    Pivot( Box<Tier3Table>, Cname, Cname, Vec<String>)//label column, value column, and the labels that become columns after the other, grouped ones
+}
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+enum WindowFn {
+    RowNumber,
+    Rank,
+    Sum(Cname),//running, over the rows up to and including the current one's peers
+    Lag(Cname,usize),
+    Lead(Cname,usize)
+}
+impl WindowFn {
+    fn sql(&self,columns:&[(String,String)])->(String,String) {
+        match self {
+            WindowFn::RowNumber=>(String::from("ROW_NUMBER()"),String::from("row_number")),
+            WindowFn::Rank=>(String::from("RANK()"),String::from("rank")),
+            WindowFn::Sum(c)=>(format!("SUM({})",columns[*c].0),format!("sum_{}",columns[*c].1)),
+            WindowFn::Lag(c,n)=>(format!("LAG({}, {})",columns[*c].0,n),format!("lag_{}",columns[*c].1)),
+            WindowFn::Lead(c,n)=>(format!("LEAD({}, {})",columns[*c].0,n),format!("lead_{}",columns[*c].1))
+        }
+    }
 }
 use Tier4Table::{*};
 
 
+//Candidate programs share most of their subtrees, so every evaluation goes through a per-example cache
+//keyed by the structural hash of the subprogram. A hit still has to be the same subprogram, since hashes can collide.
+//Named tables are shared rather than cloned. A cache made to trace lineage also keeps, for every result, the input rows
+//each of its rows came from.
+const EVAL_CACHE_CELLS:usize = 1<<22;
+type CacheEntry = (Box<dyn Any+Send>,Traced,u64);//subprogram, result, last use
+#[derive(Clone)]
+struct Traced {
+    table:Arc<Table>,
+    lineage:Option<Arc<Lineage>>
+}
+struct EvalCache<'a> {
+    tables:&'a [Table],
+    tracing:bool,
+    named:Vec<Option<Traced>>,
+    entries:HashMap<u64,CacheEntry>,
+    recent:BTreeMap<u64,u64>,//last use to hash, oldest first
+    capacity:usize,//in cells
+    used:usize,
+    tick:u64
+}
+impl<'a> EvalCache<'a> {
+    fn new(tables:&'a [Table],capacity:usize)->Self {
+        EvalCache {
+            tables,
+            tracing:false,
+            named:vec![None;tables.len()],
+            entries:HashMap::new(),
+            recent:BTreeMap::new(),
+            capacity,
+            used:0,
+            tick:0
+        }
+    }
+    fn tracing(tables:&'a [Table])->Self {
+        EvalCache {tracing:true,..EvalCache::new(tables,0)}
+    }
+    fn named(&mut self,tn:Tname)->Traced {
+        let (tables,tracing) = (self.tables,self.tracing);
+        self.named[tn].get_or_insert_with(||Traced {
+            table:Arc::new(tables[tn].clone()),
+            lineage:tracing.then(||Arc::new((0..tables[tn].rows).map(|x|vec![(tn,x)]).collect()))
+        }).clone()
+    }
+    fn memoize<Q:Hash+Eq+Clone+Any+Send>(&mut self,q:&Q,f:impl FnOnce(&mut Self)->(Table,Option<Lineage>))->Traced {
+        let mut hasher = DefaultHasher::new();
+        TypeId::of::<Q>().hash(&mut hasher);
+        q.hash(&mut hasher);
+        let key = hasher.finish();
+        self.tick+=1;
+        if let Some((program,res,lastuse)) = self.entries.get_mut(&key) {
+            if program.downcast_ref::<Q>()==Some(q) {
+                self.recent.remove(lastuse);
+                self.recent.insert(self.tick,key);
+                *lastuse = self.tick;
+                return res.clone();
+            }
+        }
+        let (table,lineage) = f(self);
+        let res = Traced {table:Arc::new(table),lineage:lineage.map(Arc::new)};
+        let size = res.table.rows*res.table.columns.len();
+        if size > self.capacity {return res;}
+        self.evict(key);//a colliding subprogram gives up its place
+        while self.used+size > self.capacity {
+            let (_,oldest) = self.recent.pop_first().unwrap();
+            self.evict(oldest);
+        }
+        self.used+=size;
+        self.recent.insert(self.tick,key);
+        self.entries.insert(key,(Box::new(q.clone()),res.clone(),self.tick));
+        res
+    }
+    fn evict(&mut self,key:u64) {
+        if let Some((_,evicted,lastuse)) = self.entries.remove(&key) {
+            self.recent.remove(&lastuse);
+            self.used -= evicted.table.rows*evicted.table.columns.len();
+        }
+    }
+}
+
+
+//Expressions evaluate a whole column at a time; any NULL operand gives NULL, as does division by zero.
+impl Arith {
+    fn apply(&self,a:f64,b:f64)->Option<f64> {
+        match self {
+            Arith::Add=>Some(a+b),
+            Arith::Sub=>Some(a-b),
+            Arith::Mul=>Some(a*b),
+            Arith::Div=>if b==0.0 {None} else {Some(a/b)}
+        }
+    }
+}
+impl Expr {
+    fn column(&self)->Option<Cname> {
+        match self {Expr::Col(c)=>Some(*c),_=>None}
+    }
+    fn shift(&self,offset:usize)->Expr {
+        self.remap(&|c|c+offset)
+    }
+    fn remap(&self,f:&dyn Fn(Cname)->Cname)->Expr {
+        let sub = |a:&Expr|Box::new(a.remap(f));
+        match self {
+            Expr::Col(c)=>Expr::Col(f(*c)),
+            Expr::Num(n)=>Expr::Num(*n),
+            Expr::Bool(b)=>Expr::Bool(*b),
+            Expr::Neg(a)=>Expr::Neg(sub(a)),
+            Expr::Arith(op,a,b)=>Expr::Arith(op.clone(),sub(a),sub(b)),
+            Expr::Round(a,d)=>Expr::Round(sub(a),*d),
+            Expr::Cast(a,to)=>Expr::Cast(sub(a),to.clone()),
+            Expr::Str(x)=>Expr::Str(x.clone()),
+            Expr::Apply(func,a)=>Expr::Apply(func.clone(),sub(a)),
+            Expr::Substr(a,start,len)=>Expr::Substr(sub(a),*start,*len),
+            Expr::Concat(parts)=>Expr::Concat(parts.iter().map(|x|x.remap(f)).collect()),
+            Expr::Replace(a,from,to)=>Expr::Replace(sub(a),from.clone(),to.clone()),
+            Expr::Time(t)=>Expr::Time(*t),
+            Expr::Extract(part,a)=>Expr::Extract(part.clone(),sub(a)),
+            Expr::Trunc(part,a)=>Expr::Trunc(part.clone(),sub(a)),
+            Expr::DateDiff(a,b)=>Expr::DateDiff(sub(a),sub(b)),
+            Expr::Interval(a,amount,unit)=>Expr::Interval(sub(a),*amount,unit.clone()),
+            Expr::Case(branches,otherwise)=>Expr::Case(
+                branches.iter().map(|(pred,x)|(pred.iter().map(|p|p.remap(f)).collect(),x.remap(f))).collect(),
+                sub(otherwise)
+            )
+        }
+    }
+}
+impl Prim {
+    fn remap(&self,f:&dyn Fn(Cname)->Cname)->Prim {
+        match self {
+            Prim::Cmp(a,op,b)=>Prim::Cmp(a.remap(f),op.clone(),b.remap(f)),
+            Prim::Like(a,pattern)=>Prim::Like(a.remap(f),pattern.clone()),
+            Prim::IsNull(c)=>Prim::IsNull(f(*c)),
+            Prim::IsNotNull(c)=>Prim::IsNotNull(f(*c)),
+            Prim::In(a,sq,ps)=>Prim::In(a.remap(f),sq.clone(),remap_outer(ps,f)),
+            Prim::NotIn(a,sq,ps)=>Prim::NotIn(a.remap(f),sq.clone(),remap_outer(ps,f)),
+            Prim::Exists(sq,ps)=>Prim::Exists(sq.clone(),remap_outer(ps,f)),
+            Prim::NotExists(sq,ps)=>Prim::NotExists(sq.clone(),remap_outer(ps,f)),
+            Prim::CmpSubquery(a,op,sq,ps)=>Prim::CmpSubquery(a.remap(f),op.clone(),sq.clone(),remap_outer(ps,f))
+        }
+    }
+    fn subquery(&self)->Option<&Tier1Table> {
+        match self {
+            Prim::In(_,sq,_)|Prim::NotIn(_,sq,_)|Prim::Exists(sq,_)|Prim::NotExists(sq,_)|Prim::CmpSubquery(_,_,sq,_)=>Some(sq),
+            _=>None
+        }
+    }
+}
+fn remap_outer(ps:&Pairs,f:&dyn Fn(Cname)->Cname)->Pairs {
+    ps.iter().map(|(o,i)|(f(*o),*i)).collect()
+}
+//integers and decimals widen to floats in arithmetic
+fn into_numeric(a:Column)->Vec<Option<f64>> {
+    match a {
+        Column::Numeric(v)=>v,
+        Column::Integer(v)=>v.into_iter().map(|x|x.map(|y|y as f64)).collect(),
+        Column::Decimal(v)=>v.into_iter().map(|x|x.map(Decimal::to_f64)).collect(),
+        _=>panic!("incorrectly typed expression")
+    }
+}
+fn into_strings(a:Column)->Vec<Option<Str>> {
+    match a {
+        Column::String(v)=>v,
+        _=>panic!("incorrectly typed expression")
+    }
+}
+fn into_times(a:Column)->Vec<Option<NaiveDateTime>> {
+    match a {
+        Column::Time(v)=>v,
+        _=>panic!("incorrectly typed expression")
+    }
+}
+//the day of month is clamped, as SQL does for '2020-01-31' + 1 month
+fn add_months(a:NaiveDateTime,months:i64)->Option<NaiveDateTime> {
+    let total = a.year() as i64*12+a.month0() as i64+months;
+    let (year,month) = (total.div_euclid(12) as i32,total.rem_euclid(12) as u32+1);
+    let date = (1..=a.day()).rev().find_map(|d|NaiveDate::from_ymd_opt(year,month,d))?;
+    Some(date.and_time(a.time()))
+}
+impl DatePart {
+    fn extract(&self,a:NaiveDateTime)->f64 {
+        match self {
+            DatePart::Year=>a.year() as f64,
+            DatePart::Month=>a.month() as f64,
+            DatePart::Day=>a.day() as f64,
+            DatePart::Hour=>a.hour() as f64
+        }
+    }
+    fn truncate(&self,a:NaiveDateTime)->NaiveDateTime {
+        let date = a.date();
+        match self {
+            DatePart::Year=>NaiveDate::from_ymd(date.year(),1,1).and_hms(0,0,0),
+            DatePart::Month=>NaiveDate::from_ymd(date.year(),date.month(),1).and_hms(0,0,0),
+            DatePart::Day=>date.and_hms(0,0,0),
+            DatePart::Hour=>date.and_hms(a.hour(),0,0)
+        }
+    }
+    fn shift(&self,a:NaiveDateTime,amount:i64)->Option<NaiveDateTime> {
+        match self {
+            DatePart::Year=>add_months(a,amount.checked_mul(12)?),
+            DatePart::Month=>add_months(a,amount),
+            DatePart::Day=>a.checked_add_signed(Duration::days(amount)),
+            DatePart::Hour=>a.checked_add_signed(Duration::hours(amount))
+        }
+    }
+    fn sql(&self)->&'static str {
+        match self {DatePart::Year=>"year",DatePart::Month=>"month",DatePart::Day=>"day",DatePart::Hour=>"hour"}
+    }
+}
+fn substring(a:&str,start:usize,len:Option<usize>)->String {
+    let skipped = a.chars().skip(start.saturating_sub(1));
+    match len {
+        Some(l)=>skipped.take(l).collect(),
+        None=>skipped.collect()
+    }
+}
+fn like(value:&[char],pattern:&[char])->bool {
+    match pattern.split_first() {
+        None=>value.is_empty(),
+        Some(('%',rest))=>(0..=value.len()).any(|i|like(&value[i..],rest)),
+        Some(('_',rest))=>!value.is_empty() && like(&value[1..],rest),
+        Some((c,rest))=>value.first()==Some(c) && like(&value[1..],rest)
+    }
+}
+fn round_to(a:f64,decimals:i32)->f64 {
+    let scale = 10f64.powi(decimals);
+    (a*scale).round()/scale
+}
+fn parse_bool(a:&str)->Option<bool> {
+    match a.to_lowercase().as_str() {
+        "true"|"t"|"yes"|"y"|"1"=>Some(true),
+        "false"|"f"|"no"|"n"|"0"=>Some(false),
+        _=>None
+    }
+}
+//casts to integers round, as Postgres does
+fn cast_column(a:Column,to:&ColumnSchema)->Column {
+    const DEFAULT_TIME:&str = "%Y-%m-%d %H:%M:%S";
+    let text = |v:&Vec<Option<Str>>|->Vec<Option<String>> {v.iter().map(|x|x.as_ref().map(|y|String::from(y.trim()))).collect()};
+    match (a,to) {
+        (Column::String(v),ColumnSchema::Numeric)=>Column::Numeric(text(&v).into_iter().map(|x|f64::from_str(&x?).ok()).collect()),
+        (Column::String(v),ColumnSchema::Time(fmt))=>Column::Time(text(&v).into_iter().map(|x|NaiveDateTime::parse_from_str(&x?,fmt).ok()).collect()),
+        (Column::String(v),ColumnSchema::Integer)=>Column::Integer(text(&v).into_iter().map(|x|i64::from_str(&x?).ok()).collect()),
+        (Column::String(v),ColumnSchema::Boolean)=>Column::Boolean(text(&v).into_iter().map(|x|parse_bool(&x?)).collect()),
+        (Column::String(v),ColumnSchema::Decimal)=>Column::Decimal(text(&v).into_iter().map(|x|Decimal::parse(&x?)).collect()),
+        (Column::Numeric(v),ColumnSchema::String)=>Column::String(v.iter().map(|x|x.map(|y|Str::from(format!("{}",y)))).collect()),
+        (Column::Time(v),ColumnSchema::String)=>Column::String(v.iter().map(|x|x.map(|y|Str::from(format!("{}",y.format(DEFAULT_TIME))))).collect()),
+        (Column::Integer(v),ColumnSchema::String)=>Column::String(v.iter().map(|x|x.map(|y|Str::from(format!("{}",y)))).collect()),
+        (Column::Boolean(v),ColumnSchema::String)=>Column::String(v.iter().map(|x|x.map(|y|Str::from(format!("{}",y)))).collect()),
+        (Column::Decimal(v),ColumnSchema::String)=>Column::String(v.iter().map(|x|x.map(|y|Str::from(format!("{}",y)))).collect()),
+        (a,ColumnSchema::Numeric) if is_numeric(&a)=>Column::Numeric(into_numeric(a)),
+        (Column::Numeric(v),ColumnSchema::Integer)=>Column::Integer(v.iter().map(|x|x.filter(|y|y.is_finite()).map(|y|y.round() as i64)).collect()),
+        (Column::Decimal(v),ColumnSchema::Integer)=>Column::Integer(v.iter().map(|x|x.map(|y|y.to_f64().round() as i64)).collect()),
+        (Column::Integer(v),ColumnSchema::Decimal)=>Column::Decimal(v.iter().map(|x|x.map(Decimal::from_int)).collect()),
+        (Column::Numeric(v),ColumnSchema::Decimal)=>Column::Decimal(v.iter().map(|x|x.and_then(Decimal::from_f64)).collect()),
+        (a,_)=>a
+    }
+}
+fn eval_expr(expr:&Expr,table:&Table)->Column {
+    match expr {
+        Expr::Col(c)=>Column::clone(&table.columns[*c]),
+        Expr::Num(n)=>Column::Numeric(vec![Some(n.0);table.rows]),
+        Expr::Bool(b)=>Column::Boolean(vec![Some(*b);table.rows]),
+        Expr::Neg(a)=>Column::Numeric(into_numeric(eval_expr(a,table)).into_iter().map(|x|x.map(|y|-y)).collect()),
+        Expr::Arith(op,a,b)=>{
+            let (a,b) = (into_numeric(eval_expr(a,table)),into_numeric(eval_expr(b,table)));
+            Column::Numeric(a.into_iter().zip(b).map(|(x,y)|op.apply(x?,y?)).collect())
+        }
+        Expr::Round(a,d)=>Column::Numeric(into_numeric(eval_expr(a,table)).into_iter().map(|x|x.map(|y|round_to(y,*d))).collect()),
+        Expr::Cast(a,to)=>cast_column(eval_expr(a,table),to),
+        Expr::Str(x)=>Column::String(vec![Some(Str::from(x.as_str()));table.rows]),
+        Expr::Apply(StringFn::Length,a)=>Column::Numeric(into_strings(eval_expr(a,table)).into_iter().map(|x|x.map(|y|y.chars().count() as f64)).collect()),
+        Expr::Apply(func,a)=>Column::String(into_strings(eval_expr(a,table)).into_iter().map(|x|x.map(|y|match func {
+            StringFn::Upper=>Str::from(y.to_uppercase()),
+            StringFn::Lower=>Str::from(y.to_lowercase()),
+            _=>Str::from(y.trim())
+        })).collect()),
+        Expr::Substr(a,start,len)=>Column::String(into_strings(eval_expr(a,table)).into_iter().map(|x|x.map(|y|Str::from(substring(&y,*start,*len)))).collect()),
+        Expr::Concat(parts)=>{
+            let mut res:Vec<Option<String>> = vec![Some(String::new());table.rows];
+            for part in parts.iter() {
+                for (r,x) in res.iter_mut().zip(into_strings(eval_expr(part,table))) {
+                    *r = match (r.take(),x) {(Some(a),Some(b))=>Some(a+&b),_=>None};
+                }
+            } Column::String(res.into_iter().map(|x|x.map(Str::from)).collect())
+        }
+        Expr::Replace(a,from,to)=>Column::String(into_strings(eval_expr(a,table)).into_iter().map(|x|x.map(|y|if from.is_empty() {y} else {Str::from(y.replace(from.as_str(),to))})).collect()),
+        Expr::Time(t)=>Column::Time(vec![Some(*t);table.rows]),
+        Expr::Extract(part,a)=>Column::Numeric(into_times(eval_expr(a,table)).into_iter().map(|x|x.map(|y|part.extract(y))).collect()),
+        Expr::Trunc(part,a)=>Column::Time(into_times(eval_expr(a,table)).into_iter().map(|x|x.map(|y|part.truncate(y))).collect()),
+        Expr::DateDiff(a,b)=>{
+            let (a,b) = (into_times(eval_expr(a,table)),into_times(eval_expr(b,table)));
+            Column::Numeric(a.into_iter().zip(b).map(|(x,y)|Some((x?-y?).num_seconds() as f64/86400.0)).collect())
+        }
+        Expr::Interval(a,amount,unit)=>Column::Time(into_times(eval_expr(a,table)).into_iter().map(|x|x.and_then(|y|unit.shift(y,*amount))).collect()),
+        Expr::Case(branches,otherwise)=>{
+            let mut res = eval_expr(otherwise,table);
+            for (pred,x) in branches.iter().rev() {
+                let mask = eval_pred(pred,table,&Subqueries::new());
+                let (widened,chosen) = widen_columns(res,eval_expr(x,table));
+                res = widened;
+                match columns_same_type_mut(&mut res,&chosen) {
+                    Some(pair)=>with_pair_mut!(pair,a,b=>overwrite_masked(a,b,&mask)),
+                    None=>panic!("incorrectly typed expression")
+                }
+            } res
+        }
+    }
+}
+fn overwrite_masked<T:Clone>(a:&mut [Option<T>],b:&[Option<T>],mask:&[bool]) {
+    for ((x,y),m) in a.iter_mut().zip(b).zip(mask) {
+        if *m {*x = y.clone();}
+    }
+}
+//results of the subqueries in a predicate, evaluated before it
+type Subqueries<'a> = HashMap<&'a Tier1Table,Arc<Table>>;
+fn correlated_rows(outer:&Table,row:usize,sub:&Table,ps:&Pairs)->Vec<usize> {
+    (0..sub.rows).filter(|r|ps.iter().all(|(o,i)|compare_op(outer,*o,row,sub,*i,*r,&Binop::Eq))).collect()
+}
+fn compare_op(a:&Table,ac:usize,ai:usize,b:&Table,bc:usize,bi:usize,op:&Binop)->bool {
+    !value_is_null(a,ac,ai) && !value_is_null(b,bc,bi) && match op {
+        Binop::Eq=>compare_table_values(a,ac,ai,b,bc,bi),
+        Binop::Neq=>!compare_table_values(a,ac,ai,b,bc,bi),
+        Binop::Lt=>compare_table_values_lt(a,ac,ai,b,bc,bi),
+        Binop::Gteq=>!compare_table_values_lt(a,ac,ai,b,bc,bi),
+        Binop::Gt=>compare_table_values_lt(b,bc,bi,a,ac,ai),
+        Binop::Lteq=>!compare_table_values_lt(b,bc,bi,a,ac,ai)
+    }
+}
+//a comparison involving NULL never holds
+fn eval_pred(pred:&Pred,table:&Table,subqueries:&Subqueries)->Vec<bool> {
+    let mut mask = vec![true;table.rows];
+    for prim in pred.iter() {
+        match prim {
+            Prim::IsNull(c)=>for (row,m) in mask.iter_mut().enumerate() {*m = *m && value_is_null(table,*c,row);},
+            Prim::IsNotNull(c)=>for (row,m) in mask.iter_mut().enumerate() {*m = *m && !value_is_null(table,*c,row);},
+            Prim::Like(a,pattern)=>{
+                let pattern:Vec<char> = pattern.chars().collect();
+                for (m,x) in mask.iter_mut().zip(into_strings(eval_expr(a,table))) {
+                    *m = *m && x.map(|y|like(&y.chars().collect::<Vec<_>>(),&pattern)).unwrap_or(false);
+                }
+            }
+            Prim::Cmp(a,op,b)=>{
+                let (left,right) = (eval_expr(a,table),eval_expr(b,table));
+                let compare = CellCompare::new(&left,&right,&EXACT);
+                for (row,m) in mask.iter_mut().enumerate() {
+                    *m = *m && compare.op(row,row,op);
+                }
+            }
+            Prim::In(a,sq,ps)|Prim::NotIn(a,sq,ps)=>{
+                let sub = subquery_result(subqueries,sq);
+                let values = Table {columns:vec![eval_expr(a,table).into()],rows:table.rows};
+                let negated = matches!(prim,Prim::NotIn(..));
+                for (row,m) in mask.iter_mut().enumerate().filter(|(_,m)|**m) {
+                    let rows = correlated_rows(table,row,sub,ps);
+                    let found = rows.iter().any(|r|compare_op(&values,0,row,sub,0,*r,&Binop::Eq));
+                    let has_null = rows.iter().any(|r|value_is_null(sub,0,*r));
+                    //against a non-empty list a NULL value, or a miss when the list holds NULL, is unknown either way
+                    *m = if rows.is_empty() {negated}
+                        else if value_is_null(&values,0,row) {false}
+                        else if found {!negated}
+                        else {negated && !has_null};
+                }
+            }
+            Prim::Exists(sq,ps)|Prim::NotExists(sq,ps)=>{
+                let sub = subquery_result(subqueries,sq);
+                let negated = matches!(prim,Prim::NotExists(..));
+                for (row,m) in mask.iter_mut().enumerate().filter(|(_,m)|**m) {
+                    *m = correlated_rows(table,row,sub,ps).is_empty() == negated;
+                }
+            }
+            Prim::CmpSubquery(a,op,sq,ps)=>{//no row or several rows compare as NULL
+                let sub = subquery_result(subqueries,sq);
+                let values = Table {columns:vec![eval_expr(a,table).into()],rows:table.rows};
+                for (row,m) in mask.iter_mut().enumerate().filter(|(_,m)|**m) {
+                    let rows = correlated_rows(table,row,sub,ps);
+                    *m = rows.len()==1 && compare_op(&values,0,row,sub,0,rows[0],op);
+                }
+            }
+        }
+    }
+    mask
+}
+fn subquery_result<'a>(subqueries:&'a Subqueries,sq:&Tier1Table)->&'a Table {
+    subqueries.get(sq).expect("subqueries are only evaluated in WHERE clauses")
+}
+fn pred_subqueries(pred:&Pred)->impl Iterator<Item=&Tier1Table> {
+    pred.iter().filter_map(|x|x.subquery())
+}
+
+//For each result row, the (input table, row) pairs it was built from.
+type Lineage = Vec<Vec<(Tname,usize)>>;
+fn lineage_through(lineage:&Lineage,origins:&[Vec<usize>])->Lineage {
+    origins.iter().map(|rows|{
+        let mut merged:Vec<(Tname,usize)> = rows.iter().flat_map(|r|lineage[*r].iter().cloned()).collect();
+        merged.sort_unstable();
+        merged.dedup();
+        merged
+    }).collect()
+}
+fn lineage_through_join(left:&Lineage,right:&Lineage,origins:&[(usize,Option<usize>)])->Lineage {
+    origins.iter().map(|(a,b)|{
+        let mut merged:Vec<(Tname,usize)> = left[*a].iter().chain(b.iter().flat_map(|b|right[*b].iter())).cloned().collect();
+        merged.sort_unstable();
+        merged.dedup();
+        merged
+    }).collect()
+}
+fn lineage_through_set(left:&Lineage,right:&Lineage,origins:&SetOrigins)->Lineage {
+    origins.iter().map(|(a,b)|{
+        let mut merged:Vec<(Tname,usize)> = a.iter().flat_map(|r|left[*r].iter()).chain(b.iter().flat_map(|r|right[*r].iter())).cloned().collect();
+        merged.sort_unstable();
+        merged.dedup();
+        merged
+    }).collect()
+}
+//an operator's result, with its lineage when its input has one
+fn traced(input:&Traced,(table,origins):(Table,Vec<Vec<usize>>))->(Table,Option<Lineage>) {
+    let lineage = input.lineage.as_ref().map(|x|lineage_through(x,&origins));
+    (table,lineage)
+}
+fn traced_join(left:&Traced,right:&Traced,(table,origins):(Table,Vec<(usize,Option<usize>)>))->(Table,Option<Lineage>) {
+    let lineage = left.lineage.as_ref().zip(right.lineage.as_ref()).map(|(l,r)|lineage_through_join(l,r,&origins));
+    (table,lineage)
+}
+
 trait Query {
-    fn evaluate(&self,tables:&Vec<Table>)->Table;
+    fn evaluate(&self,tables:&[Table])->Table {
+        let res = self.evaluate_cached(&mut EvalCache::new(tables,0));
+        Arc::try_unwrap(res).unwrap_or_else(|x|(*x).clone())
+    }
+    fn evaluate_cached(&self,cache:&mut EvalCache)->Arc<Table> {
+        self.evaluate_traced(cache).table
+    }
+    fn evaluate_with_provenance(&self,tables:&[Table])->(Table,Lineage) {
+        let res = self.evaluate_traced(&mut EvalCache::tracing(tables));
+        let lineage = res.lineage.expect("a tracing cache keeps lineage");
+        (Arc::try_unwrap(res.table).unwrap_or_else(|x|(*x).clone()),Arc::try_unwrap(lineage).unwrap_or_else(|x|(*x).clone()))
+    }
+    fn evaluate_traced(&self,cache:&mut EvalCache)->Traced;
+    fn to_sql(&self,schema:&TestCaseSchema,aliases:&mut usize)->SqlQuery;
     fn totop(self)->Tier1Table;
 }
 
+//Each operator reports, for every row it outputs, which rows of its input(s) it came from.
+fn eval_order(subq:&Table,keys:&[Key])->(Table,Vec<Vec<usize>>) {
+    let indecies = order_rows(subq,keys);
+    (table_rows(subq,&indecies),indecies.into_iter().map(|x|vec![x]).collect())
+}
+fn order_rows(subq:&Table,keys:&[Key])->Vec<usize> {
+    let compare:Vec<_> = keys.iter().map(|k|k.comparator(subq)).collect();
+    let mut indecies:Vec<usize> = (0..subq.rows).collect();
+    indecies.sort_by(|x,y|compare.iter().map(|k|k(*x,*y)).find(|o|*o!=Ordering::Equal).unwrap_or(Ordering::Equal));
+    indecies
+}
+fn eval_limit(subq:&Table,keys:&[Key],count:usize,offset:usize)->(Table,Vec<Vec<usize>>) {
+    let (ordered,origins) = eval_order(subq,keys);
+    let kept = offset.min(ordered.rows)..(offset.saturating_add(count)).min(ordered.rows);
+    (table_rows(&ordered,&kept.clone().collect::<Vec<_>>()),origins[kept].to_vec())
+}
+//equal rows, in the order of their first occurrence
+fn duplicate_groups(a:&Table)->Vec<Vec<usize>> {
+    let compare = row_comparator(a,a,&EXACT);
+    let mut groups:Vec<Vec<usize>> = Vec::new();
+    for r in sorted_rows(a,false) {
+        match groups.last_mut() {
+            Some(g) if compare(g[0],r)==Ordering::Equal=>g.push(r),
+            _=>groups.push(vec![r])
+        }
+    }
+    for g in groups.iter_mut() {g.sort();}
+    groups.sort_by_key(|g|g[0]);
+    groups
+}
+fn eval_distinct(subq:&Table,chosencols:&[Proj])->(Table,Vec<Vec<usize>>) {
+    let (projected,_) = eval_project(subq,chosencols);
+    let groups = duplicate_groups(&projected);
+    (table_rows(&projected,&groups.iter().map(|g|g[0]).collect::<Vec<_>>()),groups)
+}
+//NULLs count as equal here, as they do for SQL set operations; each result row lists the rows of either side it stands for
+type SetOrigins = Vec<(Vec<usize>,Vec<usize>)>;
+fn eval_set(subq1:&Table,subq2:&Table,op:&SetOp,all:bool)->(Table,SetOrigins) {
+    if !same_schema(subq1,subq2) {panic!("set operation over incompatible schemas")}
+    if subq1.columns.iter().zip(subq2.columns.iter()).any(|(x,y)|std::mem::discriminant(&**x)!=std::mem::discriminant(&**y)) {
+        let (mut a,mut b) = (subq1.clone(),subq2.clone());
+        for (x,y) in a.columns.iter_mut().zip(b.columns.iter_mut()) {
+            let (wx,wy) = widen_columns(Column::clone(x),Column::clone(y));
+            *x = wx.into(); *y = wy.into();
+        }
+        return eval_set(&a,&b,op,all);
+    }
+    let mut origins:Vec<(Vec<usize>,Vec<usize>)> = Vec::new();
+    let compare = row_comparator(subq1,subq2,&EXACT);
+    let compare = &compare;
+    let matching = |a:usize|(0..subq2.rows).filter(move |b|compare(a,*b)==Ordering::Equal);
+    match (op,all) {
+        (SetOp::Union,true)=>{
+            origins.extend((0..subq1.rows).map(|a|(vec![a],vec![])));
+            origins.extend((0..subq2.rows).map(|b|(vec![],vec![b])));
+        }
+        (SetOp::Union,false)=>{
+            let mut both = subq1.clone();
+            for b in 0..subq2.rows {add_row_table(&mut both,subq2,b);}
+            for g in duplicate_groups(&both) {
+                let (x,y):(Vec<usize>,Vec<usize>) = g.into_iter().partition(|r|*r<subq1.rows);
+                origins.push((x,y.into_iter().map(|r|r-subq1.rows).collect()));
+            }
+        }
+        (_,true)=>{//multiset semantics: every row of the right side cancels or matches one row of the left
+            let mut used = vec![false;subq2.rows];
+            for a in 0..subq1.rows {
+                let found = matching(a).find(|b|!used[*b]);
+                if let Some(b) = found {used[b] = true;}
+                match (op,found) {
+                    (SetOp::Intersect,Some(b))=>origins.push((vec![a],vec![b])),
+                    (SetOp::Except,None)=>origins.push((vec![a],vec![])),
+                    _=>{}
+                }
+            }
+        }
+        (_,false)=>for g in duplicate_groups(subq1) {
+            let found:Vec<usize> = matching(g[0]).collect();
+            if (*op==SetOp::Intersect) != found.is_empty() {origins.push((g,found));}
+        }
+    }
+    let mut schema = get_table_schema(subq1);
+    for (a,b) in origins.iter() {
+        match (a.first(),b.first()) {
+            (Some(r),_)=>add_row_table(&mut schema,subq1,*r),
+            (None,Some(r))=>add_row_table(&mut schema,subq2,*r),
+            (None,None)=>unreachable!()
+        }
+    }
+    (schema,origins)
+}
+fn eval_project(subq:&Table,chosencols:&[Proj])->(Table,Vec<Vec<usize>>) {
+    (Table {
+        columns:chosencols.iter().map(|(x,_)|eval_expr(x,subq).into()).collect(),
+        rows:subq.rows
+    },(0..subq.rows).map(|x|vec![x]).collect())
+}
+fn eval_select(subq:&Table,criteria:&Pred,subqueries:&Subqueries)->(Table,Vec<Vec<usize>>) {
+    let kept:Vec<usize> = eval_pred(criteria,subq,subqueries).into_iter().enumerate().filter(|(_,keep)|*keep).map(|(row,_)|row).collect();
+    (table_rows(subq,&kept),kept.into_iter().map(|x|vec![x]).collect())
+}
+fn eval_group(subq:&Table,keys:&[Expr],agg:&[Gc])->(Table,Vec<Vec<usize>>) {
+    let mut extended:Option<Table> = None;
+    let mut groupby = Vec::new();
+    for key in keys.iter() {
+        match key.column() {
+            Some(c)=>groupby.push(c),
+            None=>{
+                let t = extended.get_or_insert_with(||subq.clone());
+                t.columns.push(eval_expr(key,subq).into());
+                groupby.push(t.columns.len()-1);
+            }
+        }
+    }
+    let subq = extended.as_ref().unwrap_or(subq);
+    let mut members:Vec<Vec<usize>> = Vec::new();
+    //for every column, the row of subq each group shows in it
+    let mut shown:Vec<Vec<usize>> = vec![Vec::new();subq.columns.len()];
+    let same_key:Vec<CellCompare> = groupby.iter().map(|c|CellCompare::new(&subq.columns[*c],&subq.columns[*c],&EXACT)).collect();
+    for row in 0..subq.rows {
+        let mut found = false;
+        for lessrow in 0..members.len() {
+            if same_key.iter().all(|k|(k.eq)(members[lessrow][0],row)) {//a group's keys are those of its first row
+                found = true;
+                members[lessrow].push(row);
+                let mut firstit = true;//there's some weird niche SQL semantics that i'm emulating here
+                for (ag,agcol) in agg.iter().rev().filter(|(ag,_)|ag.in_place()) {
+                    //NULLs are skipped, so a group's extreme is NULL only when all its values are
+                    let current = shown[*agcol][lessrow];
+                    if !value_is_null(subq,*agcol,row) && (value_is_null(subq,*agcol,current) || match ag {
+                        Agg::Max=>compare_table_values_lt(subq,*agcol,current,subq,*agcol,row),
+                        Agg::Min=>compare_table_values_lt(subq,*agcol,row,subq,*agcol,current),
+                        _=>false
+                    }) {
+                        if firstit {
+                            for column in shown.iter_mut() {column[lessrow] = row;}
+                        } else {
+                            shown[*agcol][lessrow] = row;
+                        }
+                    }
+                    firstit = false;
+                }
+                break;
+            }
+        }
+        if !found {
+            for column in shown.iter_mut() {column.push(row);}
+            members.push(vec![row]);
+        }
+    }
+    //columns showing the same rows share one selection
+    let mut selections:Vec<(Vec<usize>,Selection,Vec<_>)> = Vec::new();
+    let mut columns = Vec::new();
+    for (column,rows) in subq.columns.iter().zip(shown) {
+        let at = match selections.iter().position(|(x,_,_)|*x==rows) {
+            Some(at)=>at,
+            None=>{
+                let selection = rows.iter().map(|r|Some(*r)).collect();
+                selections.push((rows,selection,Vec::new()));
+                selections.len()-1
+            }
+        };
+        let (_,selection,composed) = &mut selections[at];
+        columns.push(column.select(selection,composed));
+    }
+    let mut schema = Table {columns,rows:members.len()};
+    //COUNT is an integer and SUM keeps an exact input type, as in Postgres; AVG is always a float
+    for (ag,agcol) in agg.iter().filter(|(ag,_)|!ag.in_place()) {
+        schema.columns.push(match (ag,&*subq.columns[*agcol]) {
+            (Agg::Count,_)=>Column::Integer(members.iter().map(|rows|Some(rows.iter().filter(|r|!value_is_null(subq,*agcol,**r)).count() as i64)).collect()),
+            (Agg::Sum,column@(Column::Integer(_)|Column::Decimal(_)))=>sum_rows(column,&members.iter().map(|x|x.as_slice()).collect::<Vec<_>>()),
+            (_,column)=>{
+                let v = if is_numeric(column) {into_numeric(column.clone())} else {vec![None;subq.rows]};
+                Column::Numeric(members.iter().map(|rows|{
+                    let present:Vec<f64> = rows.iter().filter_map(|r|v[*r]).collect();
+                    if present.is_empty() {None}
+                    else if *ag==Agg::Sum {Some(present.iter().sum())}
+                    else {Some(present.iter().sum::<f64>()/present.len() as f64)}
+                }).collect())
+            }
+        }.into());
+    }
+    (schema,members)
+}
+//SUM over each set of rows, NULL for a set without values. As SUM(bigint) in Postgres, integers sum to decimals, so
+//no sum overflows; decimals keep their type.
+fn sum_rows(column:&Column,sets:&[&[usize]])->Column {
+    match column {
+        Column::Integer(v)=>Column::Decimal(sets.iter().map(|rows|{
+            rows.iter().filter_map(|r|v[*r]).fold(None,|acc:Option<i128>,x|Some(acc.unwrap_or(0)+x as i128)).map(|units|Decimal {units,scale:0})
+        }).collect()),
+        Column::Decimal(v)=>Column::Decimal(sets.iter().map(|rows|{//a sum that overflows is NULL
+            rows.iter().filter_map(|r|v[*r]).try_fold(None,|acc:Option<Decimal>,x|match acc {None=>Some(Some(x)),Some(y)=>y.add(x).map(Some)}).flatten()
+        }).collect()),
+        _=>{
+            let v = into_numeric(column.clone());
+            Column::Numeric(sets.iter().map(|rows|{
+                let present:Vec<f64> = rows.iter().filter_map(|r|v[*r]).collect();
+                if present.is_empty() {None} else {Some(present.iter().sum())}
+            }).collect())
+        }
+    }
+}
+fn eval_window(subq:&Table,partition:&[Cname],keys:&[Key],func:&WindowFn)->(Table,Vec<Vec<usize>>) {
+    let same_values = |cs:Vec<Cname>|{
+        let cells:Vec<CellCompare> = cs.into_iter().map(|c|CellCompare::new(&subq.columns[c],&subq.columns[c],&EXACT)).collect();
+        move |a:usize,b:usize|cells.iter().all(|x|(x.eq)(a,b))
+    };
+    let (same,peers) = (same_values(partition.to_owned()),same_values(keys.iter().map(|k|k.column()).collect()));
+    let mut partitions:Vec<Vec<usize>> = Vec::new();//rows of each partition in window order
+    for r in order_rows(subq,keys) {
+        match partitions.iter_mut().find(|p|same(p[0],r)) {
+            Some(p)=>p.push(r),
+            None=>partitions.push(vec![r])
+        }
+    }
+    let mut numbers:Vec<Option<i64>> = vec![None;subq.rows];
+    let mut frames:Vec<&[usize]> = vec![&[];subq.rows];//rows a running SUM covers
+    let mut shifted:Vec<Option<usize>> = vec![None;subq.rows];
+    for p in partitions.iter() {
+        let mut rank = 0;
+        for (i,r) in p.iter().enumerate() {
+            if i==0 || !peers(p[i-1],*r) {rank = i+1;}
+            match func {
+                WindowFn::RowNumber=>numbers[*r] = Some((i+1) as i64),
+                WindowFn::Rank=>numbers[*r] = Some(rank as i64),
+                WindowFn::Sum(_)=>{
+                    let last = (i..p.len()).take_while(|j|peers(p[*j],*r)).last().unwrap();
+                    frames[*r] = &p[..=last];
+                }
+                WindowFn::Lag(_,n)=>shifted[*r] = i.checked_sub(*n).map(|j|p[j]),
+                WindowFn::Lead(_,n)=>shifted[*r] = p.get(i+n).cloned()
+            }
+        }
+    }
+    let mut res = subq.clone();
+    res.columns.push(match func {
+        WindowFn::Lag(c,_)|WindowFn::Lead(c,_)=>{
+            let source = Table {columns:vec![subq.columns[*c].clone()],rows:subq.rows};
+            table_rows_padded(&source,&shifted).columns.pop().unwrap()
+        }
+        WindowFn::Sum(c)=>sum_rows(&subq.columns[*c],&frames).into(),
+        _=>Column::Integer(numbers).into()
+    });
+    (res,(0..subq.rows).map(|x|vec![x]).collect())
+}
+//every row becomes one row per value column, in column order; NULL values are kept
+fn eval_unpivot(subq:&Table,values:&[(Cname,String)])->(Table,Vec<Vec<usize>>) {
+    let kept = Table {
+        columns:(0..subq.columns.len()).filter(|c|!values.iter().any(|(v,_)|v==c)).map(|c|subq.columns[c].clone()).collect(),
+        rows:subq.rows
+    };
+    let rows:Vec<usize> = (0..subq.rows).flat_map(|r|iter::repeat_n(r,values.len())).collect();
+    let mut res = table_rows(&kept,&rows);
+    let labels:Vec<Str> = values.iter().map(|(_,label)|Str::from(label.as_str())).collect();
+    res.columns.push(Column::String((0..rows.len()).map(|i|Some(labels[i%values.len()].clone())).collect()).into());
+    let mut stacked = subq.columns[values[0].0].clone();//the value columns one after another
+    for (c,_) in values[1..].iter() {
+        with_pair_mut!(columns_same_type_mut(&mut stacked,&subq.columns[*c]).expect("unpivot over differently typed columns"),a,b=>a.extend(b.iter().cloned()));
+    }
+    let cells:Vec<usize> = rows.iter().enumerate().map(|(i,r)|(i%values.len())*subq.rows+r).collect();
+    res.columns.push(table_rows(&Table {columns:vec![stacked],rows:subq.rows*values.len()},&cells).columns.pop().unwrap());
+    (res,rows.into_iter().map(|x|vec![x]).collect())
+}
+//groups on every other column; each label's column holds the largest value among the group's rows whose label
+//column reads as it, so repeated labels behave like MAX(CASE WHEN ... END)
+fn eval_pivot(subq:&Table,key:Cname,value:Cname,labels:&[String])->(Table,Vec<Vec<usize>>) {
+    let rest:Vec<Cname> = (0..subq.columns.len()).filter(|c|*c!=key && *c!=value).collect();
+    let (grouped,members) = eval_group(subq,&rest.iter().map(|c|Expr::Col(*c)).collect::<Vec<_>>(),&[]);
+    let mut res = Table {columns:rest.iter().map(|c|grouped.columns[*c].clone()).collect(),rows:grouped.rows};
+    let names = into_strings(cast_column(Column::clone(&subq.columns[key]),&ColumnSchema::String));
+    let source = Table {columns:vec![subq.columns[value].clone()],rows:subq.rows};
+    let compare = CellCompare::new(&source.columns[0],&source.columns[0],&EXACT);
+    for label in labels.iter() {
+        let best:Vec<Option<usize>> = members.iter().map(|group|{
+            let mut best:Option<usize> = None;
+            for r in group.iter().filter(|r|names[**r].as_deref()==Some(label.as_str()) && !value_is_null(&source,0,**r)) {
+                if best.is_none_or(|b|(compare.lt)(b,*r)) {best = Some(*r);}
+            }
+            best
+        }).collect();
+        res.columns.push(table_rows_padded(&source,&best).columns.pop().unwrap());
+    }
+    (res,members)
+}
+fn eval_join(subq1:&Table,subq2:&Table,ps:&Pairs)->(Table,Vec<(usize,Option<usize>)>) {
+    let keys:Vec<CellCompare> = ps.iter().map(|(c1,c2)|CellCompare::new(&subq1.columns[*c1],&subq2.columns[*c2],&EXACT)).collect();
+    let mut origins = Vec::new();
+    for a in 0..subq1.rows {
+        for b in 0..subq2.rows {
+            if keys.iter().all(|k|k.op(a,b,&Binop::Eq)) {origins.push((a,Some(b)));}
+        }
+    }
+    (joined_rows(subq1,subq2,&origins),origins)
+}
+fn joined_rows(subq1:&Table,subq2:&Table,origins:&[(usize,Option<usize>)])->Table {
+    let (left,right):(Vec<usize>,Vec<Option<usize>>) = origins.iter().cloned().unzip();
+    table_glue(table_rows(subq1,&left),table_rows_padded(subq2,&right))
+}
+fn eval_left_join(subq1:&Table,subq2:&Table,ps:&Pair)->(Table,Vec<(usize,Option<usize>)>) {
+    let (c1,c2) = ps;
+    let key = CellCompare::new(&subq1.columns[*c1],&subq2.columns[*c2],&EXACT);
+    let mut origins = Vec::new();
+    for a in 0..subq1.rows {
+        let mut foundone = false;
+        for b in 0..subq2.rows {
+            if key.op(a,b,&Binop::Eq) {
+                origins.push((a,Some(b)));
+                foundone = true;
+            }
+        }
+        if !foundone {origins.push((a,None));}
+    }
+    (joined_rows(subq1,subq2,&origins),origins)
+}
+
 impl Query for Tier1Table {
-    fn evaluate(&self,tables:&Vec<Table>)->Table {
+    fn evaluate_traced(&self,cache:&mut EvalCache)->Traced {
+        match self {
+            Tier1Table::Order(sq,keys) => cache.memoize(self,|cache|{
+                let subq = sq.evaluate_traced(cache);
+                traced(&subq,eval_order(&subq.table,keys))
+            }),
+            Tier1Table::Limit(sq,keys,count,offset) => cache.memoize(self,|cache|{
+                let subq = sq.evaluate_traced(cache);
+                traced(&subq,eval_limit(&subq.table,keys,*count,*offset))
+            }),
+            Tier1Table::Set(op,all,sq1,sq2) => cache.memoize(self,|cache|{
+                let subq1 = sq1.evaluate_traced(cache);
+                let subq2 = sq2.evaluate_traced(cache);
+                let (res,origins) = eval_set(&subq1.table,&subq2.table,op,*all);
+                let lineage = subq1.lineage.as_ref().zip(subq2.lineage.as_ref()).map(|(lineage1,lineage2)|lineage_through_set(lineage1,lineage2,&origins));
+                (res,lineage)
+            }),
+            Tier1Table::N(sq) => sq.evaluate_traced(cache)
+        }
+    }
+    fn to_sql(&self,schema:&TestCaseSchema,aliases:&mut usize)->SqlQuery {
         match self {
             Tier1Table::Order(sq,keys) => {
-                let subq = sq.evaluate(tables);
-                let mut indecies:Vec<usize> = (0..subq.rows).collect();
-                indecies.sort_by(|x,y|{
-                    for key in keys {
-                        match key {
-                            Key::Asc(col)=>{
-                                if compare_table_values(&subq,*col,*x,&subq,*col,*y) {continue;}
-                                return if compare_table_values_lt(&subq,*col,*x,&subq,*col,*y)
-                                    {Ordering::Less} else {Ordering::Greater};
-                            },
-                            Key::Desc(col)=>{
-                                if compare_table_values(&subq,*col,*x,&subq,*col,*y) {continue;}
-                                return if compare_table_values_lt(&subq,*col,*x,&subq,*col,*y)
-                                    {Ordering::Greater} else {Ordering::Less};
-                            }
-                        }
-                    }
-                    return Ordering::Equal;
-                });
-                let mut schema = get_table_schema(&subq);
-                for a in indecies.iter() {add_row_table(&mut schema,&subq,*a);}
-                return schema;
+                let mut q = sq.to_sql(schema,aliases);
+                if !q.order.is_empty() {q = q.wrap(aliases);}
+                q.order = keys.iter().map(|k|k.sql(&q.columns)).collect();
+                q
+            }
+            Tier1Table::Limit(sq,keys,count,offset) => {
+                let mut q = Tier1Table::Order(sq.clone(),keys.clone()).to_sql(schema,aliases);
+                q.limit = Some((*count,*offset));
+                q
             }
-            Tier1Table::N(sq) => sq.evaluate(tables)
+            Tier1Table::Set(op,all,sq1,sq2) => {
+                let mut l = sq1.to_sql(schema,aliases);
+                let names = l.unique_names();
+                let r = sq2.to_sql(schema,aliases);
+                let keyword = match op {SetOp::Union=>"UNION",SetOp::Intersect=>"INTERSECT",SetOp::Except=>"EXCEPT"};
+                let alias = next_alias(aliases);
+                SqlQuery::from(
+                    format!("(({}) {}{} ({})) AS {}",l.render(),keyword,if *all {" ALL"} else {""},r.render(),alias),
+                    l.selectable(&alias,names)
+                )
+            }
+            Tier1Table::N(sq) => sq.to_sql(schema,aliases)
         }
     }
     fn totop(self)->Tier1Table {self}
 }
 impl Query for Tier2Table {
-    fn evaluate(&self,tables:&Vec<Table>)->Table {
+    fn evaluate_traced(&self,cache:&mut EvalCache)->Traced {
+        match self {
+            Tier2Table::Project(sq,chosencols) => cache.memoize(self,|cache|{
+                let subq = sq.evaluate_traced(cache);
+                traced(&subq,eval_project(&subq.table,chosencols))
+            }),
+            Tier2Table::Distinct(sq,chosencols) => cache.memoize(self,|cache|{
+                let subq = sq.evaluate_traced(cache);
+                traced(&subq,eval_distinct(&subq.table,chosencols))
+            }),
+            Tier2Table::N(sq) => sq.evaluate_traced(cache)
+        }
+    }
+    fn to_sql(&self,schema:&TestCaseSchema,aliases:&mut usize)->SqlQuery {
         match self {
             Tier2Table::Project(sq,chosencols) => {
-                let subq = sq.evaluate(tables);
-                return Table {
-                    columns:chosencols.iter().map(|x|subq.columns[*x].clone()).collect(),
-                    rows:subq.rows
-                };
+                let mut q = sq.to_sql(schema,aliases);
+                q.columns = chosencols.iter().enumerate().map(|(i,(x,alias))|(
+                    expr_sql(x,&q.columns),
+                    alias.clone().or_else(||x.column().map(|c|q.columns[c].1.clone())).unwrap_or_else(||format!("column{}",i+1))
+                )).collect();
+                q
             }
-            Tier2Table::N(sq) => sq.evaluate(tables)
+            Tier2Table::Distinct(sq,chosencols) => {
+                let mut q = Tier2Table::Project(sq.clone(),chosencols.clone()).to_sql(schema,aliases);
+                q.distinct = true;
+                q
+            }
+            Tier2Table::N(sq) => sq.to_sql(schema,aliases)
         }
     }
     fn totop(self)->Tier1Table {Tier1Table::N(self)}
 }
+impl Tier2Table {
+    //synthesis works with unnamed columns; the output's names are given once a program is found
+    fn project(sq:Tier3Table,cols:Vec<Expr>)->Self {
+        Tier2Table::Project(sq,cols.into_iter().map(|x|(x,None)).collect())
+    }
+}
 impl Query for Tier3Table {
-    fn evaluate(&self,tables:&Vec<Table>)->Table {
+    fn evaluate_traced(&self,cache:&mut EvalCache)->Traced {
+        match self {
+            Tier3Table::Select(sq,criteria) => cache.memoize(self,|cache|{
+                let subqueries:Subqueries = pred_subqueries(criteria).map(|x|(x,x.evaluate_cached(cache))).collect();
+                let subq = sq.evaluate_traced(cache);
+                traced(&subq,eval_select(&subq.table,criteria,&subqueries))
+            }),
+            Tier3Table::N(sq) => sq.evaluate_traced(cache)
+        }
+    }
+    fn to_sql(&self,schema:&TestCaseSchema,aliases:&mut usize)->SqlQuery {
         match self {
             Tier3Table::Select(sq,criteria) => {
-                let subq = sq.evaluate(tables);
-                let mut schema = get_table_schema(&subq);
-                //TODO
-                return schema;
+                let mut q = sq.to_sql(schema,aliases);
+                if q.windowed {q = q.wrap(aliases);}
+                let columns = q.columns.clone();
+                let conditions:Vec<String> = criteria.iter().map(|x|prim_sql(x,&columns,&mut |sub,ps,first_only|{
+                    subquery_sql(sub.to_sql(schema,aliases),ps,&columns,first_only,aliases)
+                })).collect();
+                if q.grouped {q.having.extend(conditions);} else {q.filter.extend(conditions);}//a filter over groups can refer to the aggregates
+                q
             }
-            Tier3Table::N(sq) => sq.evaluate(tables)
+            Tier3Table::N(sq) => sq.to_sql(schema,aliases)
         }
     }
     fn totop(self)->Tier1Table {Tier1Table::N(Tier2Table::N(self))}
 }
 impl Query for Tier4Table {
-    fn evaluate(&self,tables:&Vec<Table>)->Table {
+    fn evaluate_traced(&self,cache:&mut EvalCache)->Traced {
+        match self {
+            Tier4Table::Named(tn)=>cache.named(*tn),
+            Tier4Table::Group(sq,groupby,agg)=>cache.memoize(self,|cache|{
+                let subq = sq.evaluate_traced(cache);
+                traced(&subq,eval_group(&subq.table,groupby,agg))
+            }),
+            Tier4Table::Join( sq1, sq2, ps)=>cache.memoize(self,|cache|{
+                let subq1 = sq1.evaluate_traced(cache);
+                let subq2 = sq2.evaluate_traced(cache);
+                traced_join(&subq1,&subq2,eval_join(&subq1.table,&subq2.table,ps))
+            }),
+            Tier4Table::LeftJoin( sq1, sq2, ps)=>cache.memoize(self,|cache|{
+                let subq1 = sq1.evaluate_traced(cache);
+                let subq2 = sq2.evaluate_traced(cache);
+                traced_join(&subq1,&subq2,eval_left_join(&subq1.table,&subq2.table,ps))
+            }),
+            Tier4Table::Window(sq,partition,keys,func)=>cache.memoize(self,|cache|{
+                let subq = sq.evaluate_traced(cache);
+                traced(&subq,eval_window(&subq.table,partition,keys,func))
+            }),
+            Tier4Table::Unpivot(sq,values)=>cache.memoize(self,|cache|{
+                let subq = sq.evaluate_traced(cache);
+                traced(&subq,eval_unpivot(&subq.table,values))
+            }),
+            Tier4Table::Pivot(sq,key,value,labels)=>cache.memoize(self,|cache|{
+                let subq = sq.evaluate_traced(cache);
+                traced(&subq,eval_pivot(&subq.table,*key,*value,labels))
+            })
+        }
+    }
+    fn to_sql(&self,schema:&TestCaseSchema,aliases:&mut usize)->SqlQuery {
         match self {
-            Tier4Table::Named(tn)=>tables[*tn].clone(),
+            Tier4Table::Named(tn)=>{
+                let alias = next_alias(aliases);
+                let table = &schema.inputs[*tn];
+                SqlQuery::from(
+                    format!("{} AS {}",quote_ident(&table.name),alias),
+                    table.columns.iter().map(|(x,_)|(format!("{}.{}",alias,quote_ident(x)),x.clone())).collect()
+                )
+            }
             Tier4Table::Group(sq,groupby,agg)=>{
-                let subq = sq.evaluate(tables);
-                let mut schema = get_table_schema(&subq);
-                for row in 0..subq.rows {
-                    let mut found = false;
-                    for lessrow in 0..schema.rows {
-                        let mut samerec = true;
-                        for factor in groupby.iter() {
-                            if !compare_table_values(&schema,*factor,lessrow,&subq,*factor,row) {samerec=false;}
-                        }
-                        if samerec {
-                            found = true;
-                            let mut firstit = true;//there's some weird niche SQL semantics that i'm emulating here
-                            for (ag,agcol) in agg.iter().rev() {
-                                if match ag {
-                                    Agg::Max=>compare_table_values_lt(&schema,*agcol,lessrow,&subq,*agcol,row),
-                                    Agg::Min=>compare_table_values_lt(&subq,*agcol,row,&schema,*agcol,lessrow)
-                                } {
-                                    if firstit {
-                                        for col in 0..subq.columns.len() {
-                                            move_table_values(&mut schema,col,lessrow,&subq,col,row);
-                                        }
-                                    } else {
-                                        move_table_values(&mut schema,*agcol,lessrow,&subq,*agcol,row);
-                                    }
-                                }
-                                firstit = false;
-                            }
-                            break;
-                        }
-                    }
-                    if !found {
-                        add_row_table(&mut schema,&subq,row);
-                    }
+                let mut q = sq.to_sql(schema,aliases);
+                if q.grouped || q.windowed || !q.order.is_empty() {q = q.wrap(aliases);}
+                let width = q.columns.len();
+                q.group = groupby.iter().map(|x|expr_sql(x,&q.columns)).collect();
+                for i in (0..groupby.len()).filter(|i|groupby[*i].column().is_none()) {
+                    q.columns.push((q.group[i].clone(),format!("key{}",i+1)));
                 }
-                return schema;
-            },
-            Tier4Table::Join( sq1, sq2, ps)=>{
-                let subq1 = sq1.evaluate(tables);
-                let subq2 = sq2.evaluate(tables);
-                let mut lside = get_table_schema(&subq1);
-                let mut rside = get_table_schema(&subq2);
-                for a in 0..subq1.rows {
-                    for b in 0..subq2.rows {
-                        if ps.iter().all(|(c1,c2)|compare_table_values(&subq1,*c1,a,&subq2,*c2,b)) {
-                            add_row_table(&mut lside,&subq1,a);
-                            add_row_table(&mut rside,&subq2,b);
-                        }
-                    }
+                let appended:Vec<(String,String)> = agg.iter().filter(|(ag,_)|!ag.in_place()).map(|(ag,agcol)|{
+                    (format!("{}({})",ag.sql(),q.columns[*agcol].0),format!("{}_{}",ag.sql().to_lowercase(),q.columns[*agcol].1))
+                }).collect();
+                for (ag,agcol) in agg.iter().filter(|(ag,_)|ag.in_place()) {
+                    q.columns[*agcol].0 = format!("{}({})",ag.sql(),q.columns[*agcol].0);
                 }
-                return table_glue(lside,rside);
-            },
-            Tier4Table::LeftJoin( sq1, sq2, ps)=>{
-                let subq1 = sq1.evaluate(tables);
-                let subq2 = sq2.evaluate(tables);
-                let (c1,c2) = ps;
-                let mut lside = get_table_schema(&subq1);
-                let mut rside = get_table_schema(&subq2);
-                for a in 0..subq1.rows {
-                    let mut foundone = false;
-                    for b in 0..subq2.rows {
-                        if compare_table_values(&subq1,*c1,a,&subq2,*c2,b) {
-                            add_row_table(&mut lside,&subq1,a);
-                            add_row_table(&mut rside,&subq2,b);
-                            foundone = true;
-                        }
-                    }
-                    if !foundone {
-                        add_row_table(&mut lside,&subq1,a);
-                        add_null_row(&mut rside);
-                    }
+                //columns neither grouped nor aggregated hold the extreme or first row of their group, which SQL cannot select
+                for c in (0..width).filter(|c|!groupby.contains(&Expr::Col(*c)) && !agg.iter().any(|(ag,agcol)|ag.in_place() && agcol==c)) {
+                    q.columns[c].0 = String::from(UNSELECTABLE);
                 }
-                return table_glue(lside,rside);
+                q.columns.extend(appended);
+                q.grouped = true;
+                q
+            }
+            Tier4Table::Join( sq1, sq2, ps)=>{
+                let (l,r) = (sq1.to_sql(schema,aliases).plain(aliases),sq2.to_sql(schema,aliases).plain(aliases));
+                let on:Vec<String> = ps.iter().map(|(a,b)|format!("{} = {}",l.columns[*a].0,r.columns[*b].0)).collect();
+                SqlQuery::from(format!("{} JOIN {} ON {}",l.from,r.from,on.join(" AND ")),l.columns.into_iter().chain(r.columns).collect())
+            }
+            Tier4Table::LeftJoin( sq1, sq2, ps)=>{
+                let (l,r) = (sq1.to_sql(schema,aliases).plain(aliases),sq2.to_sql(schema,aliases).plain(aliases));
+                let on = format!("{} = {}",l.columns[ps.0].0,r.columns[ps.1].0);
+                SqlQuery::from(format!("{} LEFT JOIN {} ON {}",l.from,r.from,on),l.columns.into_iter().chain(r.columns).collect())
+            }
+            Tier4Table::Window(sq,partition,keys,func)=>{
+                let mut q = sq.to_sql(schema,aliases);
+                if q.windowed || !q.order.is_empty() {q = q.wrap(aliases);}
+                let mut over:Vec<String> = Vec::new();
+                if !partition.is_empty() {over.push(format!("PARTITION BY {}",partition.iter().map(|c|q.columns[*c].0.clone()).collect::<Vec<_>>().join(", ")));}
+                if !keys.is_empty() {over.push(format!("ORDER BY {}",keys.iter().map(|k|k.sql(&q.columns)).collect::<Vec<_>>().join(", ")));}
+                let (call,name) = func.sql(&q.columns);
+                q.columns.push((format!("{} OVER ({})",call,over.join(" ")),name));
+                q.windowed = true;
+                q
+            }
+            Tier4Table::Unpivot(sq,values)=>{
+                let q = sq.to_sql(schema,aliases).plain(aliases);
+                let alias = next_alias(aliases);
+                let rows:Vec<String> = values.iter().map(|(c,label)|format!("({}, {})",quote_str(label),q.columns[*c].0)).collect();
+                let mut columns:Vec<(String,String)> = q.columns.iter().enumerate().filter(|(c,_)|!values.iter().any(|(v,_)|v==c)).map(|(_,x)|x.clone()).collect();
+                columns.push((format!("{}.\"name\"",alias),String::from("name")));
+                columns.push((format!("{}.\"value\"",alias),String::from("value")));
+                SqlQuery::from(format!("{} CROSS JOIN LATERAL (VALUES {}) AS {}(\"name\", \"value\")",q.from,rows.join(", "),alias),columns)
+            }
+            Tier4Table::Pivot(sq,key,value,labels)=>{
+                let mut q = sq.to_sql(schema,aliases);
+                if q.grouped || q.windowed || !q.order.is_empty() {q = q.wrap(aliases);}
+                let rest:Vec<(String,String)> = q.columns.iter().enumerate().filter(|(c,_)|c!=key && c!=value).map(|(_,x)|x.clone()).collect();
+                q.group = rest.iter().map(|(x,_)|x.clone()).collect();
+                let pivoted = labels.iter().map(|label|{
+                    (format!("MAX(CASE WHEN CAST({} AS TEXT) = {} THEN {} END)",q.columns[*key].0,quote_str(label),q.columns[*value].0),label.clone())
+                });
+                q.columns = rest.iter().cloned().chain(pivoted).collect();
+                q.grouped = true;
+                q
             }
         }
     }
@@ -249,112 +1305,608 @@ impl Query for Tier4Table {
 
 
 
-#[derive(Debug,Clone)]
-enum Column {
-    String(Vec<Option<String>>),
-    Numeric(Vec<Option<f64>>),
-    Time(Vec<Option<NaiveDateTime>>)
-}
-enum ColumnPair<'a> {
-    String(&'a Vec<Option<String>>,&'a Vec<Option<String>>),
-    Numeric(&'a Vec<Option<f64>>,&'a Vec<Option<f64>>),
-    Time(&'a Vec<Option<NaiveDateTime>>,&'a Vec<Option<NaiveDateTime>>)
-}
-fn columns_same_type<'a>(a:&'a Column,b:&'a Column)->Option<ColumnPair<'a>> {
-    match (a,b) {
-        (Column::String(a),Column::String(b))=>Some(ColumnPair::String(a,b)),
-        (Column::Numeric(a),Column::Numeric(b))=>Some(ColumnPair::Numeric(a,b)),
-        (Column::Time(a),Column::Time(b))=>Some(ColumnPair::Time(a,b)),
-        _=>None
-    }
+//SQL rendering. Each operator extends a single SELECT while it can, and nests it as a subquery when it can't.
+struct SqlQuery {
+    from:String,
+    columns:Vec<(String,String)>,//expression, name
+    filter:Vec<String>,
+    group:Vec<String>,
+    having:Vec<String>,
+    grouped:bool,
+    windowed:bool,//window functions see the rows after WHERE, so later filters go outside
+    distinct:bool,
+    order:Vec<String>,
+    limit:Option<(usize,usize)>
 }
-enum ColumnPairMut<'a> {
-    String(&'a mut Vec<Option<String>>,&'a Vec<Option<String>>),
-    Numeric(&'a mut Vec<Option<f64>>,&'a Vec<Option<f64>>),
-    Time(&'a mut Vec<Option<NaiveDateTime>>,&'a Vec<Option<NaiveDateTime>>)
+//stands in for the expression of a column SQL cannot select; it is left out of SELECT lists, and SQL using it is rejected
+const UNSELECTABLE:&str = "\u{0}";
+fn next_alias(aliases:&mut usize)->String {
+    *aliases+=1;
+    format!("t{}",aliases)
 }
-fn columns_same_type_mut<'a>(a:&'a mut Column,b:&'a Column)->Option<ColumnPairMut<'a>> {
-    match (a,b) {
-        (Column::String(a),Column::String(b))=>Some(ColumnPairMut::String(a,b)),
-        (Column::Numeric(a),Column::Numeric(b))=>Some(ColumnPairMut::Numeric(a,b)),
-        (Column::Time(a),Column::Time(b))=>Some(ColumnPairMut::Time(a,b)),
-        _=>None
-    }
+fn quote_ident(a:&str)->String {
+    format!("\"{}\"",a.replace('"',"\"\""))
 }
-fn compare_table_values_full<'a>(a:&'a Table,b:&'a Table)->bool {
-    if a.columns.len() != b.columns.len() || a.rows != b.rows {return false;}
-    a.columns.iter().zip(b.columns.iter()).all(|(cola,colb)|{
-        match columns_same_type(&cola,&colb) {
-            None=>panic!("incorrectly typed comparison"),
-            Some(ColumnPair::String(ac,bc)) => ac.iter().zip(bc.iter()).all(|(a,b)|a==b),
-            Some(ColumnPair::Numeric(ac,bc)) => ac.iter().zip(bc.iter()).all(|(a,b)|a==b),
-            Some(ColumnPair::Time(ac,bc)) => ac.iter().zip(bc.iter()).all(|(a,b)|a==b)
-        }
-    })
+fn quote_str(a:&str)->String {
+    format!("'{}'",a.replace('\'',"''"))
 }
-fn compare_table_values<'a>(a:&'a Table,ac:usize,ai:usize,b:&'a Table,bc:usize,bi:usize)->bool {
-    match columns_same_type(&a.columns[ac],&b.columns[bc]) {
-        None=>panic!("incorrectly typed comparison"),
-        Some(ColumnPair::String(ac,bc)) => ac[ai]==bc[bi],
-        Some(ColumnPair::Numeric(ac,bc)) => ac[ai]==bc[bi],
-        Some(ColumnPair::Time(ac,bc)) => ac[ai]==bc[bi]
+impl SqlQuery {
+    fn from(from:String,columns:Vec<(String,String)>)->Self {
+        SqlQuery {from,columns,filter:Vec::new(),group:Vec::new(),having:Vec::new(),grouped:false,windowed:false,distinct:false,order:Vec::new(),limit:None}
+    }
+    fn render(&self)->String {
+        let columns:Vec<String> = self.columns.iter().filter(|(x,_)|x!=UNSELECTABLE).map(|(x,name)|{
+            if x.ends_with(&format!(".{}",quote_ident(name))) {x.clone()} else {format!("{} AS {}",x,quote_ident(name))}
+        }).collect();
+        let mut res = format!("SELECT {}{} FROM {}",if self.distinct {"DISTINCT "} else {""},columns.join(", "),self.from);
+        if !self.filter.is_empty() {res+=&format!(" WHERE {}",self.filter.join(" AND "));}
+        if !self.group.is_empty() {res+=&format!(" GROUP BY {}",self.group.join(", "));}
+        if !self.having.is_empty() {res+=&format!(" HAVING {}",self.having.join(" AND "));}
+        if !self.order.is_empty() {res+=&format!(" ORDER BY {}",self.order.join(", "));}
+        match self.limit {
+            Some((count,0))=>res+=&format!(" LIMIT {}",count),
+            Some((count,offset))=>res+=&format!(" LIMIT {} OFFSET {}",count,offset),
+            None=>{}
+        }
+        res
+    }
+    //renames repeated output columns so the query can be selected from
+    fn unique_names(&mut self)->Vec<String> {
+        let mut names:Vec<String> = Vec::new();
+        for (_,name) in self.columns.iter() {
+            let mut unique = name.clone();
+            let mut n = 1;
+            while names.contains(&unique) {n+=1;unique = format!("{}_{}",name,n);}
+            names.push(unique);
+        }
+        for ((_,name),unique) in self.columns.iter_mut().zip(names.iter()) {*name = unique.clone();}
+        names
+    }
+    //the columns of this query as selected from outside it, left without an expression where it has none
+    fn selectable(&self,alias:&str,names:Vec<String>)->Vec<(String,String)> {
+        self.columns.iter().zip(names).map(|((x,_),name)|(if x==UNSELECTABLE {x.clone()} else {format!("{}.{}",alias,quote_ident(&name))},name)).collect()
+    }
+    fn wrap(mut self,aliases:&mut usize)->SqlQuery {
+        let names = self.unique_names();
+        let alias = next_alias(aliases);
+        SqlQuery::from(
+            format!("({}) AS {}",self.render(),alias),
+            self.selectable(&alias,names)
+        )
+    }
+    //a bare FROM clause that can take part in a join
+    fn plain(self,aliases:&mut usize)->SqlQuery {
+        if self.filter.is_empty() && !self.grouped && !self.windowed && !self.distinct && self.order.is_empty() && self.limit.is_none() {self} else {self.wrap(aliases)}
     }
 }
-fn compare_table_values_lt<'a>(a:&'a Table,ac:usize,ai:usize,b:&'a Table,bc:usize,bi:usize)->bool {
+fn expr_sql(expr:&Expr,columns:&Vec<(String,String)>)->String {
+    let sub = |a:&Expr|expr_sql(a,columns);
+    match expr {
+        Expr::Col(c)=>columns[*c].0.clone(),
+        Expr::Num(n)=>format!("{}",n.0),
+        Expr::Bool(b)=>String::from(if *b {"TRUE"} else {"FALSE"}),
+        Expr::Neg(a)=>format!("-({})",sub(a)),
+        Expr::Arith(Arith::Div,a,b)=>format!("(CAST({} AS DOUBLE PRECISION) / {})",sub(a),sub(b)),//evaluated as floats, where Postgres divides integers
+        Expr::Arith(op,a,b)=>format!("({} {} {})",sub(a),match op {Arith::Add=>"+",Arith::Sub=>"-",Arith::Mul=>"*",Arith::Div=>"/"},sub(b)),
+        Expr::Round(a,d)=>format!("ROUND(CAST({} AS NUMERIC), {})",sub(a),d),
+        Expr::Cast(a,to)=>format!("CAST({} AS {})",sub(a),match to {ColumnSchema::String=>"TEXT",ColumnSchema::Numeric=>"DOUBLE PRECISION",ColumnSchema::Time(_)=>"TIMESTAMP",ColumnSchema::Integer=>"INTEGER",ColumnSchema::Boolean=>"BOOLEAN",ColumnSchema::Decimal=>"NUMERIC"}),
+        Expr::Str(x)=>quote_str(x),
+        Expr::Apply(func,a)=>format!("{}({})",match func {StringFn::Upper=>"UPPER",StringFn::Lower=>"LOWER",StringFn::Trim=>"TRIM",StringFn::Length=>"LENGTH"},sub(a)),
+        Expr::Substr(a,start,Some(len))=>format!("SUBSTR({}, {}, {})",sub(a),start,len),
+        Expr::Substr(a,start,None)=>format!("SUBSTR({}, {})",sub(a),start),
+        Expr::Concat(parts)=>format!("({})",parts.iter().map(sub).collect::<Vec<_>>().join(" || ")),
+        Expr::Replace(a,from,to)=>format!("REPLACE({}, {}, {})",sub(a),quote_str(from),quote_str(to)),
+        Expr::Time(t)=>format!("TIMESTAMP {}",quote_str(&format!("{}",t.format("%Y-%m-%d %H:%M:%S")))),
+        Expr::Extract(part,a)=>format!("EXTRACT({} FROM {})",part.sql().to_uppercase(),sub(a)),
+        Expr::Trunc(part,a)=>format!("DATE_TRUNC({}, {})",quote_str(part.sql()),sub(a)),
+        Expr::DateDiff(a,b)=>format!("(EXTRACT(EPOCH FROM ({} - {})) / 86400)",sub(a),sub(b)),
+        Expr::Interval(a,amount,unit)=>format!("({} + INTERVAL {})",sub(a),quote_str(&format!("{} {}",amount,unit.sql()))),
+        Expr::Case(branches,otherwise) if branches.is_empty()=>sub(otherwise),
+        Expr::Case(branches,otherwise)=>{
+            let whens:Vec<String> = branches.iter().map(|(pred,x)|{
+                let conditions:Vec<String> = pred.iter().map(|p|prim_sql(p,columns,&mut |_,_,_|panic!("subqueries are only rendered in WHERE clauses"))).collect();
+                format!("WHEN {} THEN {}",conditions.join(" AND "),sub(x))
+            }).collect();
+            format!("CASE {} ELSE {} END",whens.join(" "),sub(otherwise))
+        }
+    }
+}
+//a subquery with its correlation conditions added to its WHERE clause
+fn subquery_sql(q:SqlQuery,ps:&Pairs,outer:&[(String,String)],first_only:bool,aliases:&mut usize)->String {
+    let mut q = if ps.is_empty() || !(q.grouped || q.windowed || q.distinct || !q.order.is_empty() || q.limit.is_some()) {q} else {q.wrap(aliases)};
+    let conditions:Vec<String> = ps.iter().map(|(o,i)|format!("{} = {}",q.columns[*i].0,outer[*o].0)).collect();
+    q.filter.extend(conditions);
+    if first_only {q.columns.truncate(1);}
+    format!("({})",q.render())
+}
+fn prim_sql(prim:&Prim,columns:&Vec<(String,String)>,subquery:&mut dyn FnMut(&Tier1Table,&Pairs,bool)->String)->String {
+    let binop = |op:&Binop|match op {
+        Binop::Eq=>"=",Binop::Lt=>"<",Binop::Lteq=>"<=",Binop::Gt=>">",Binop::Gteq=>">=",Binop::Neq=>"<>"
+    };
+    match prim {
+        Prim::Cmp(a,op,b)=>format!("{} {} {}",expr_sql(a,columns),binop(op),expr_sql(b,columns)),
+        Prim::Like(a,pattern)=>format!("{} LIKE {}",expr_sql(a,columns),quote_str(pattern)),
+        Prim::IsNull(c)=>format!("{} IS NULL",columns[*c].0),
+        Prim::IsNotNull(c)=>format!("{} IS NOT NULL",columns[*c].0),
+        Prim::In(a,sq,ps)=>format!("{} IN {}",expr_sql(a,columns),subquery(sq,ps,true)),
+        Prim::NotIn(a,sq,ps)=>format!("{} NOT IN {}",expr_sql(a,columns),subquery(sq,ps,true)),
+        Prim::Exists(sq,ps)=>format!("EXISTS {}",subquery(sq,ps,false)),
+        Prim::NotExists(sq,ps)=>format!("NOT EXISTS {}",subquery(sq,ps,false)),
+        Prim::CmpSubquery(a,op,sq,ps)=>format!("{} {} {}",expr_sql(a,columns),binop(op),subquery(sq,ps,true))
+    }
+}
+fn program_sql(expr:&Tier1Table,schema:&TestCaseSchema)->Result<String,String> {
+    let sql = expr.to_sql(schema,&mut 0).render();
+    if sql.contains(UNSELECTABLE) {return Err(String::from("the program reads a column that is neither grouped nor aggregated"));}
+    Ok(sql)
+}
+
+column_types!{define_columns!{}}
+//Strings read into the tables of one example share a dictionary that numbers each distinct text once, so two of its
+//strings are equal exactly when their ids are and copying one only bumps a count. Strings computed by expressions
+//belong to no dictionary and compare by text.
+#[derive(Debug,Clone)]
+struct Str {
+    text:Arc<str>,
+    dictionary:u32,//0 for none
+    id:u32
+}
+impl Str {
+    fn as_str(&self)->&str {
+        &self.text
+    }
+    fn same_dictionary(&self,other:&Str)->bool {
+        self.dictionary!=0 && self.dictionary==other.dictionary
+    }
+}
+impl PartialEq for Str {
+    fn eq(&self,other:&Self)->bool {
+        if self.same_dictionary(other) {self.id==other.id} else {self.text==other.text}
+    }
+}
+impl Eq for Str {}
+impl PartialOrd for Str {
+    fn partial_cmp(&self,other:&Self)->Option<Ordering> {Some(self.cmp(other))}
+}
+impl Ord for Str {
+    fn cmp(&self,other:&Self)->Ordering {
+        if self==other {Ordering::Equal} else {self.text.cmp(&other.text)}
+    }
+}
+impl Hash for Str {
+    fn hash<H:Hasher>(&self,state:&mut H) {self.text.hash(state)}
+}
+impl std::ops::Deref for Str {
+    type Target = str;
+    fn deref(&self)->&str {&self.text}
+}
+impl std::fmt::Display for Str {
+    fn fmt(&self,f:&mut std::fmt::Formatter)->std::fmt::Result {write!(f,"{}",self.text)}
+}
+impl From<String> for Str {
+    fn from(a:String)->Str {Str {text:a.into(),dictionary:0,id:0}}
+}
+impl From<&str> for Str {
+    fn from(a:&str)->Str {Str {text:a.into(),dictionary:0,id:0}}
+}
+static DICTIONARIES:AtomicU32 = AtomicU32::new(1);
+struct Dictionary {
+    number:u32,
+    ids:HashMap<Arc<str>,u32>
+}
+impl Dictionary {
+    fn new()->Dictionary {
+        Dictionary {number:DICTIONARIES.fetch_add(1,AtomicOrdering::Relaxed),ids:HashMap::new()}
+    }
+    fn intern(&mut self,a:&str)->Str {
+        if let Some((text,id)) = self.ids.get_key_value(a) {return Str {text:text.clone(),dictionary:self.number,id:*id};}
+        let (text,id):(Arc<str>,u32) = (a.into(),self.ids.len() as u32);
+        self.ids.insert(text.clone(),id);
+        Str {text,dictionary:self.number,id}
+    }
+}
+//Fixed-point numbers, units of 10^-scale. Values keep the scale they were written with and compare by value.
+#[derive(Debug,Clone,Copy)]
+struct Decimal {
+    units:i128,
+    scale:u32
+}
+impl Decimal {
+    fn parse(a:&str)->Option<Decimal> {
+        let (sign,digits) = match a.strip_prefix('-') {Some(x)=>(-1,x),None=>(1,a.strip_prefix('+').unwrap_or(a))};
+        let (whole,fraction) = digits.split_once('.').unwrap_or((digits,""));
+        if whole.len()+fraction.len()==0 || !whole.chars().chain(fraction.chars()).all(|c|c.is_ascii_digit()) {return None;}
+        let units = format!("{}{}",whole,fraction).parse::<i128>().ok()?;
+        Some(Decimal {units:sign*units,scale:fraction.len() as u32})
+    }
+    fn from_int(a:i64)->Decimal {
+        Decimal {units:a as i128,scale:0}
+    }
+    fn from_f64(a:f64)->Option<Decimal> {
+        if a.is_finite() {Decimal::parse(&format!("{}",a))} else {None}
+    }
+    //None when the units no longer fit at that scale
+    fn units_at(&self,scale:u32)->Option<i128> {
+        10i128.checked_pow(scale-self.scale).and_then(|x|self.units.checked_mul(x))
+    }
+    fn to_f64(self)->f64 {
+        self.units as f64/10f64.powi(self.scale as i32)
+    }
+    fn add(self,other:Decimal)->Option<Decimal> {
+        let scale = self.scale.max(other.scale);
+        Some(Decimal {units:self.units_at(scale)?.checked_add(other.units_at(scale)?)?,scale})
+    }
+}
+impl PartialEq for Decimal {
+    fn eq(&self,other:&Self)->bool {self.partial_cmp(other)==Some(Ordering::Equal)}
+}
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self,other:&Self)->Option<Ordering> {
+        let scale = self.scale.max(other.scale);
+        match (self.units_at(scale),other.units_at(scale)) {
+            (Some(a),Some(b))=>a.partial_cmp(&b),
+            _=>self.to_f64().partial_cmp(&other.to_f64())//too far apart in scale to line up exactly
+        }
+    }
+}
+impl std::fmt::Display for Decimal {
+    fn fmt(&self,f:&mut std::fmt::Formatter)->std::fmt::Result {
+        let digits = format!("{:0>width$}",self.units.abs(),width = self.scale as usize+1);
+        let (whole,fraction) = digits.split_at(digits.len()-self.scale as usize);
+        write!(f,"{}{}{}{}",if self.units<0 {"-"} else {""},whole,if self.scale>0 {"."} else {""},fraction)
+    }
+}
+//Integers, decimals and floats compare with each other by value. Integers and decimals stay exact between themselves;
+//anything against a float is compared as floats.
+#[derive(Debug,Clone,Copy,PartialEq)]
+enum Widened {
+    Exact(Decimal),
+    Float(f64)
+}
+impl Widened {
+    fn to_f64(self)->f64 {
+        match self {Widened::Exact(x)=>x.to_f64(),Widened::Float(x)=>x}
+    }
+}
+impl PartialOrd for Widened {
+    fn partial_cmp(&self,other:&Self)->Option<Ordering> {
+        match (self,other) {
+            (Widened::Exact(a),Widened::Exact(b))=>a.partial_cmp(b),
+            (a,b)=>a.to_f64().partial_cmp(&b.to_f64())
+        }
+    }
+}
+#[derive(Clone,Copy)]
+enum NumericRef<'a> {
+    Numeric(&'a Vec<Option<f64>>),
+    Integer(&'a Vec<Option<i64>>),
+    Decimal(&'a Vec<Option<Decimal>>)
+}
+impl<'a> NumericRef<'a> {
+    fn of(a:&'a Column)->Option<NumericRef<'a>> {
+        match a {
+            Column::Numeric(v)=>Some(NumericRef::Numeric(v)),
+            Column::Integer(v)=>Some(NumericRef::Integer(v)),
+            Column::Decimal(v)=>Some(NumericRef::Decimal(v)),
+            _=>None
+        }
+    }
+    fn get(&self,i:usize)->Option<Widened> {
+        match self {
+            NumericRef::Numeric(v)=>v[i].map(Widened::Float),
+            NumericRef::Integer(v)=>v[i].map(|x|Widened::Exact(Decimal::from_int(x))),
+            NumericRef::Decimal(v)=>v[i].map(Widened::Exact)
+        }
+    }
+    fn widened(&self)->Vec<Option<Widened>> {
+        let len = match self {NumericRef::Numeric(v)=>v.len(),NumericRef::Integer(v)=>v.len(),NumericRef::Decimal(v)=>v.len()};
+        (0..len).map(|i|self.get(i)).collect()
+    }
+}
+fn is_numeric(a:&Column)->bool {
+    NumericRef::of(a).is_some()
+}
+//the common type two comparable columns are combined in, as when a CASE or UNION mixes integers and floats
+fn widen_columns(a:Column,b:Column)->(Column,Column) {
+    if std::mem::discriminant(&a)==std::mem::discriminant(&b) || !is_numeric(&a) || !is_numeric(&b) {return (a,b);}
+    let to = if matches!(a,Column::Numeric(_)) || matches!(b,Column::Numeric(_)) {ColumnSchema::Numeric} else {ColumnSchema::Decimal};
+    (cast_column(a,&to),cast_column(b,&to))
+}
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+struct Tolerance {
+    absolute:f64,
+    relative:f64,
+    rounding:bool//a value written with n decimals also matches anything that rounds to it
+}
+const EXACT:Tolerance = Tolerance {absolute:0.0,relative:0.0,rounding:false};
+impl Default for Tolerance {
+    fn default()->Self {EXACT}
+}
+impl Tolerance {
+    fn numeric_eq(&self,a:f64,b:f64)->bool {
+        if a==b {return true;}
+        let diff = (a-b).abs();
+        if diff<=self.absolute || diff<=self.relative*a.abs().max(b.abs()) {return true;}
+        self.rounding && (rounds_to(a,b) || rounds_to(b,a))
+    }
+}
+fn decimals(a:f64)->Option<i32> {
+    (0..=10).find(|d|{
+        let scaled = a*10f64.powi(*d);
+        (scaled-scaled.round()).abs()<=1e-9*scaled.abs().max(1.0)
+    })
+}
+//integers are left alone; only values written with some decimals are treated as rounded
+fn rounds_to(actual:f64,written:f64)->bool {
+    match decimals(written) {
+        Some(d) if d>0 => {
+            let scale = 10f64.powi(d);
+            ((actual*scale).round()/scale-written).abs()<=0.5/scale*1e-6
+        }
+        _=>false
+    }
+}
+trait Approx {
+    fn approx_eq(&self,other:&Self,tol:&Tolerance)->bool;
+}
+impl Approx for Str {
+    fn approx_eq(&self,other:&Self,_tol:&Tolerance)->bool {self==other}
+}
+impl Approx for NaiveDateTime {
+    fn approx_eq(&self,other:&Self,_tol:&Tolerance)->bool {self==other}
+}
+impl Approx for f64 {
+    fn approx_eq(&self,other:&Self,tol:&Tolerance)->bool {tol.numeric_eq(*self,*other)}
+}
+impl Approx for i64 {
+    fn approx_eq(&self,other:&Self,tol:&Tolerance)->bool {self==other || tol.numeric_eq(*self as f64,*other as f64)}
+}
+impl Approx for bool {
+    fn approx_eq(&self,other:&Self,_tol:&Tolerance)->bool {self==other}
+}
+impl Approx for Decimal {
+    fn approx_eq(&self,other:&Self,tol:&Tolerance)->bool {self==other || tol.numeric_eq(self.to_f64(),other.to_f64())}
+}
+impl Approx for Widened {
+    fn approx_eq(&self,other:&Self,tol:&Tolerance)->bool {
+        self.partial_cmp(other)==Some(Ordering::Equal) || tol.numeric_eq(self.to_f64(),other.to_f64())
+    }
+}
+impl<T:Approx> Approx for Option<T> {
+    fn approx_eq(&self,other:&Self,tol:&Tolerance)->bool {
+        match (self,other) {
+            (Some(a),Some(b))=>a.approx_eq(b,tol),
+            (a,b)=>a.is_none() && b.is_none()
+        }
+    }
+}
+fn compare_table_values_full<'a>(a:&'a Table,b:&'a Table,tol:&Tolerance)->bool {
+    if !same_schema(a,b) || a.rows != b.rows {return false;}
+    (0..a.columns.len()).all(|c|(0..a.rows).all(|r|compare_table_values_tol(a,c,r,b,c,r,tol)))
+}
+//Compares cells of two columns for loops over many rows. Their types are matched once for the pair, so each cell costs
+//an indirect call rather than a match on both columns. Equality and order treat NULLs as compare_table_values does;
+//op treats them as compare_op does.
+struct CellCompare<'a> {
+    eq:Box<dyn Fn(usize,usize)->bool+'a>,
+    lt:Box<dyn Fn(usize,usize)->bool+'a>,
+    null:Box<dyn Fn(usize,usize)->bool+'a>//either side is NULL
+}
+impl<'a> CellCompare<'a> {
+    fn new(a:&'a Column,b:&'a Column,tol:&'a Tolerance)->CellCompare<'a> {
+        match columns_same_type(a,b) {
+            None=>panic!("incorrectly typed comparison"),
+            Some(pair)=>with_pair!(pair,x,y=>CellCompare::typed(x.as_slice(),y.as_slice(),tol);
+                widened x,y=>CellCompare::typed(Arc::<[_]>::from(x.widened()),Arc::<[_]>::from(y.widened()),tol))
+        }
+    }
+    fn typed<T:PartialOrd+Approx+'a,V:AsRef<[Option<T>]>+Clone+'a>(a:V,b:V,tol:&'a Tolerance)->CellCompare<'a> {
+        let (a2,b2,a3,b3) = (a.clone(),b.clone(),a.clone(),b.clone());
+        CellCompare {
+            eq:Box::new(move |i,j|a.as_ref()[i].approx_eq(&b.as_ref()[j],tol)),
+            lt:Box::new(move |i,j|a2.as_ref()[i]<b2.as_ref()[j]),
+            null:Box::new(move |i,j|a3.as_ref()[i].is_none() || b3.as_ref()[j].is_none())
+        }
+    }
+    fn ordering(&self,i:usize,j:usize)->Ordering {
+        if (self.eq)(i,j) {Ordering::Equal} else if (self.lt)(i,j) {Ordering::Less} else {Ordering::Greater}
+    }
+    fn op(&self,i:usize,j:usize,op:&Binop)->bool {
+        !(self.null)(i,j) && match op {
+            Binop::Eq=>(self.eq)(i,j),
+            Binop::Neq=>!(self.eq)(i,j),
+            Binop::Lt=>(self.lt)(i,j),
+            Binop::Gteq=>!(self.lt)(i,j),
+            Binop::Gt=>!(self.lt)(i,j) && !(self.eq)(i,j),
+            Binop::Lteq=>(self.lt)(i,j) || (self.eq)(i,j)
+        }
+    }
+}
+//compare_rows for many pairs of rows of the same two tables
+fn row_comparator<'a>(a:&'a Table,b:&'a Table,tol:&'a Tolerance)->impl Fn(usize,usize)->Ordering+'a {
+    columns_comparator(a,b,&(0..a.columns.len()).collect::<Vec<_>>(),tol)
+}
+//as row_comparator, looking only at some of the columns
+fn columns_comparator<'a>(a:&'a Table,b:&'a Table,cols:&[usize],tol:&'a Tolerance)->impl Fn(usize,usize)->Ordering+'a {
+    let cells:Vec<CellCompare> = cols.iter().map(|c|CellCompare::new(&a.columns[*c],&b.columns[*c],tol)).collect();
+    move |ai,bi|cells.iter().map(|c|c.ordering(ai,bi)).find(|o|*o!=Ordering::Equal).unwrap_or(Ordering::Equal)
+}
+fn compare_rows(a:&Table,ai:usize,b:&Table,bi:usize,tol:&Tolerance)->Ordering {
+    for col in 0..a.columns.len() {
+        if compare_table_values_tol(a,col,ai,b,col,bi,tol) {continue;}
+        return if compare_table_values_lt(a,col,ai,b,col,bi) {Ordering::Less} else {Ordering::Greater};
+    }
+    Ordering::Equal
+}
+fn sorted_rows(a:&Table,distinct:bool)->Vec<usize> {
+    let compare = row_comparator(a,a,&EXACT);
+    let mut indecies:Vec<usize> = (0..a.rows).collect();
+    indecies.sort_by(|x,y|compare(*x,*y));
+    if distinct {indecies.dedup_by(|x,y|compare(*x,*y)==Ordering::Equal);}
+    indecies
+}
+//the rows of a table sorted on some of its columns, in runs that are equal on those columns
+fn sorted_runs(a:&Table,cols:&[usize],rows:Vec<usize>)->Vec<Vec<usize>> {
+    let compare = columns_comparator(a,a,cols,&EXACT);
+    let mut rows = rows;
+    rows.sort_by(|x,y|compare(*x,*y));
+    let mut runs:Vec<Vec<usize>> = Vec::new();
+    for r in rows {
+        match runs.last_mut() {
+            Some(run) if compare(run[0],r)==Ordering::Equal=>run.push(r),
+            _=>runs.push(vec![r])
+        }
+    }
+    runs
+}
+//whether every left row can be paired with its own right row, by augmenting paths
+fn perfect_matching(left:usize,right:usize,pairs:impl Fn(usize,usize)->bool)->bool {
+    fn augment(l:usize,right:usize,pairs:&dyn Fn(usize,usize)->bool,seen:&mut [bool],matched:&mut [Option<usize>])->bool {
+        for r in 0..right {
+            if seen[r] || !pairs(l,r) {continue;}
+            seen[r] = true;
+            if matched[r].is_none_or(|other|augment(other,right,pairs,seen,matched)) {
+                matched[r] = Some(l);
+                return true;
+            }
+        }
+        false
+    }
+    let mut matched = vec![None;right];
+    left==right && (0..left).all(|l|augment(l,right,&pairs,&mut vec![false;right],&mut matched))
+}
+//as many columns, each pair of a type that compares
+fn same_schema(a:&Table,b:&Table)->bool {
+    a.columns.len()==b.columns.len() && a.columns.iter().zip(b.columns.iter()).all(|(x,y)|columns_same_type(x,y).is_some())
+}
+//Values within the tolerance of each other can sort apart, so rows are only sorted on the columns compared exactly.
+//Rows that agree on those are then matched one to one on the numeric columns, or just found for Set.
+fn compare_tables(a:&Table,b:&Table,mode:Comparison,tol:&Tolerance)->bool {
+    if mode==Comparison::Ordered {return compare_table_values_full(a,b,tol);}
+    if !same_schema(a,b) {return false;}
+    let distinct = mode==Comparison::Set;
+    let inexact = tol.absolute>0.0 || tol.relative>0.0 || tol.rounding;
+    let (loose,exact):(Vec<usize>,Vec<usize>) = (0..a.columns.len()).partition(|c|inexact && is_numeric(&a.columns[*c]));
+    if loose.is_empty() {
+        let (arows,brows) = (sorted_rows(a,distinct),sorted_rows(b,distinct));
+        let compare = row_comparator(a,b,tol);
+        return arows.len()==brows.len() && arows.iter().zip(brows.iter()).all(|(x,y)|compare(*x,*y)==Ordering::Equal);
+    }
+    let (aruns,bruns) = (sorted_runs(a,&exact,sorted_rows(a,distinct)),sorted_runs(b,&exact,sorted_rows(b,distinct)));
+    let (same_run,close) = (columns_comparator(a,b,&exact,&EXACT),columns_comparator(a,b,&loose,tol));
+    aruns.len()==bruns.len() && aruns.iter().zip(bruns.iter()).all(|(x,y)|{
+        let pairs = |i:usize,j:usize|close(x[i],y[j])==Ordering::Equal;
+        same_run(x[0],y[0])==Ordering::Equal && if distinct {
+            (0..x.len()).all(|i|(0..y.len()).any(|j|pairs(i,j))) && (0..y.len()).all(|j|(0..x.len()).any(|i|pairs(i,j)))
+        } else {
+            perfect_matching(x.len(),y.len(),pairs)
+        }
+    })
+}
+//Two NULLs count as the same value here, as they do for grouping, DISTINCT and set operations.
+//Comparisons in joins and predicates go through compare_op, where NULL matches nothing.
+fn compare_table_values<'a>(a:&'a Table,ac:usize,ai:usize,b:&'a Table,bc:usize,bi:usize)->bool {
+    compare_table_values_tol(a,ac,ai,b,bc,bi,&EXACT)
+}
+fn compare_table_values_tol<'a>(a:&'a Table,ac:usize,ai:usize,b:&'a Table,bc:usize,bi:usize,tol:&Tolerance)->bool {
     match columns_same_type(&a.columns[ac],&b.columns[bc]) {
         None=>panic!("incorrectly typed comparison"),
-        Some(ColumnPair::String(ac,bc)) => ac[ai]<bc[bi],
-        Some(ColumnPair::Numeric(ac,bc)) => ac[ai]<bc[bi],
-        Some(ColumnPair::Time(ac,bc)) => ac[ai]<bc[bi]
+        Some(pair)=>with_pair!(pair,ac,bc=>ac[ai].approx_eq(&bc[bi],tol); widened ac,bc=>ac.get(ai).approx_eq(&bc.get(bi),tol))
     }
 }
-fn move_table_values<'a>(a:&'a mut Table,ac:usize,ai:usize,b:&'a Table,bc:usize,bi:usize) {
-    match columns_same_type_mut(& mut a.columns[ac],&b.columns[bc]) {
-        None=>panic!("incorrectly typed assignment"),
-        Some(ColumnPairMut::String(ac,bc)) => ac[ai]=bc[bi].clone(),
-        Some(ColumnPairMut::Numeric(ac,bc)) => ac[ai]=bc[bi],
-        Some(ColumnPairMut::Time(ac,bc)) => ac[ai]=bc[bi]
+fn compare_table_values_lt<'a>(a:&'a Table,ac:usize,ai:usize,b:&'a Table,bc:usize,bi:usize)->bool {
+    match columns_same_type(&a.columns[ac],&b.columns[bc]) {
+        None=>panic!("incorrectly typed comparison"),
+        Some(pair)=>with_pair!(pair,ac,bc=>ac[ai]<bc[bi]; widened ac,bc=>ac.get(ai)<bc.get(bi))
     }
 }
 #[derive(Debug, Clone)]
 struct Table {
-    columns:Vec<Column>,
+    columns:Vec<LazyColumn>,
     rows:usize
 }
+//A column that may be a selection of the rows of a base column. Selecting from a selection picks from the same base,
+//so operators only pass row numbers along, and cells are copied when the column is first read, if ever.
+type Selection = Arc<[Option<usize>]>;
+#[derive(Debug,Clone)]
+struct LazyColumn {
+    base:Arc<Column>,
+    rows:Option<Selection>,//None for all of base in order; a None row reads as NULL
+    gathered:OnceLock<Arc<Column>>
+}
+impl From<Column> for LazyColumn {
+    fn from(a:Column)->LazyColumn {
+        LazyColumn {base:Arc::new(a),rows:None,gathered:OnceLock::new()}
+    }
+}
+impl LazyColumn {
+    fn gathered(&self)->&Arc<Column> {
+        match &self.rows {
+            None=>&self.base,
+            Some(rows)=>self.gathered.get_or_init(||Arc::new(map_same!(&*self.base,v=>rows.iter().map(|r|r.and_then(|r|v[r].clone())).collect())))
+        }
+    }
+    //the rows picked, through every selection, from the base; a selection shared by several columns is composed once
+    fn select(&self,rows:&Selection,composed:&mut Vec<(Selection,Selection)>)->LazyColumn {
+        let picked = match &self.rows {
+            None=>rows.clone(),
+            Some(own)=>match composed.iter().find(|(x,_)|Arc::ptr_eq(x,own)) {
+                Some((_,picked))=>picked.clone(),
+                None=>{
+                    let picked:Selection = rows.iter().map(|r|r.and_then(|r|own[r])).collect();
+                    composed.push((own.clone(),picked.clone()));
+                    picked
+                }
+            }
+        };
+        LazyColumn {base:self.base.clone(),rows:Some(picked),gathered:OnceLock::new()}
+    }
+}
+impl std::ops::Deref for LazyColumn {
+    type Target = Column;
+    fn deref(&self)->&Column {
+        self.gathered()
+    }
+}
+impl std::ops::DerefMut for LazyColumn {
+    fn deref_mut(&mut self)->&mut Column {
+        if self.rows.is_some() {
+            self.base = self.gathered().clone();
+            self.rows = None;
+            self.gathered = OnceLock::new();
+        }
+        Arc::make_mut(&mut self.base)
+    }
+}
 fn get_table_schema(t:&Table)->Table {
-    return Table {
-        columns:t.columns.iter().map(|x|match x {
-            Column::String(_)=>Column::String(vec![]),
-            Column::Numeric(_)=>Column::Numeric(vec![]),
-            Column::Time(_)=>Column::Time(vec![]),
-        }).collect(),
+    Table {
+        columns:t.columns.iter().map(|x|map_same!(&**x,_v=>vec![]).into()).collect(),
         rows:0
     }
 }
 fn table_glue(a:Table,b:Table)->Table {
     if a.rows != b.rows {panic!("Tried to combine two tables with different numbers of rows")}
-    return Table {
-        columns:a.columns.into_iter().chain(b.columns.into_iter()).collect(),
+    Table {
+        columns:a.columns.into_iter().chain(b.columns).collect(),
         rows:a.rows
     }
 }
+//the given rows of a table, in order, as selections of its columns
+fn table_rows(a:&Table,rows:&[usize])->Table {
+    table_rows_padded(a,&rows.iter().map(|r|Some(*r)).collect::<Vec<_>>())
+}
+//as table_rows, with an all-NULL row for every None
+fn table_rows_padded(a:&Table,rows:&[Option<usize>])->Table {
+    let rows:Selection = rows.into();
+    let mut composed = Vec::new();
+    Table {columns:a.columns.iter().map(|x|x.select(&rows,&mut composed)).collect(),rows:rows.len()}
+}
 fn add_row_table(a:&mut Table,b:&Table,c:usize) {
     for column in 0..a.columns.len() {
         match columns_same_type_mut(&mut a.columns[column],&b.columns[column]) {
             None=>panic!("Tried to add row to table of incorrect schema"),
-            Some(ColumnPairMut::String(ac,bc)) => ac.push(bc[c].clone()),
-            Some(ColumnPairMut::Numeric(ac,bc)) => ac.push(bc[c]),
-            Some(ColumnPairMut::Time(ac,bc)) => ac.push(bc[c])
-        }
-    }
-    a.rows+=1;
-}
-fn add_null_row(a:&mut Table) {
-    for column in 0..a.columns.len() {
-        match &mut a.columns[column] {
-            Column::String(ac) => ac.push(None),
-            Column::Numeric(ac) => ac.push(None),
-            Column::Time(ac) => ac.push(None)
+            Some(pair)=>with_pair_mut!(pair,ac,bc=>ac.push(bc[c].clone()))
         }
     }
     a.rows+=1;
@@ -381,79 +1933,131 @@ fn add_null_row(a:&mut Table) {
 type Pairing = Vec<Vec<usize>>;
 struct LifeTimeLessEdgeRef {
     weight:LinkGraphEdge,
-    id:EdgeIndex,
-    source:NodeIndex,
     target:NodeIndex
 }
 fn remove_lifetime(a:EdgeReference<LinkGraphEdge>)->LifeTimeLessEdgeRef {
-    return LifeTimeLessEdgeRef {
+    LifeTimeLessEdgeRef {
         weight:a.weight().clone(),
-        id:a.id(),
-        source:a.source(),
         target:a.target()
     }
 }
 #[derive(Clone)]
 struct PathAssociated {
-    // forward:Pairing,
+    root:usize,
+    root_col:usize,
     backward:Pairing,
     forward_match:RowMapping,
     path:Vec<(usize,LinkGraphEdge)>,//target table, from column, to column
-    available_targets:Box<[Box<[bool]>]>
+    available_targets:Box<[Box<[Option<usize>]>]>//position along the path where each target is satisfied
 }
 impl PathAssociated {
-    fn concat_path_associated(&self,over:&LinkGraphEdge,totable:usize,targets: &[Vec<(RowMapping,usize,usize)>])->Option<Self> {
+    fn root(inputs:&[Table],targets:&Targets,col:usize,option:usize)->Self {
+        let (forward_match,table,expr) = &targets[col][option];
+        let mut available_targets:Box<[Box<[Option<usize>]>]> = targets.iter().map(|x|vec![None;x.len()].into_boxed_slice()).collect();
+        mark_available_targets(&mut available_targets,forward_match,*table,0,targets);
+        PathAssociated {
+            root:*table,
+            root_col:expr.column().unwrap_or(usize::MAX),
+            backward:(0..inputs[*table].rows).map(|x|vec![x]).collect(),
+            forward_match:forward_match.clone(),
+            path:Vec::new(),
+            available_targets
+        }
+    }
+    fn concat_path_associated(&self,over:&LinkGraphEdge,totable:usize,targets:&Targets)->Option<Self> {
         let (formatch,disabled) = translate_map_across_pair(&self.forward_match,&over.forward_pairing);
+        if !disabled.is_empty() {return None;}//an inner join would lose these output rows
         let mut avail2 = self.available_targets.clone();
-        if disabled.len()!=0 {
-            let mut encountered_sol = false;
-            for i in 0..targets.len() {
-                for j in 0..targets[i].len() {
-                    if avail2[i][j] {
-                        for disable in disabled.iter() {
-                            if !targets[i][j].0[*disable].1 {
-                                avail2[i][j]=false;
-                                break;
-                            }
-                        }
-                        if avail2[i][j] {encountered_sol = true;}
-                    }
-                }
-            }
-            if !encountered_sol {return None;}
-        }
+        mark_available_targets(&mut avail2,&formatch,totable,self.path.len()+1,targets);
         Some(PathAssociated {
-            // forward:concatenate_pairings(&self.forward,&over.forward_pairing),
+            root:self.root,
+            root_col:self.root_col,
             backward:concatenate_pairings(&over.backward_pairing,&self.backward),
             forward_match:formatch,
             path:self.path.iter().cloned().chain(iter::once((totable,over.clone()))).collect(),
             available_targets:avail2
         })
     }
+    fn visited(&self)->Vec<(usize,usize)> {
+        let mut states = vec![(self.root,self.root_col)];
+        let mut at = self.root;
+        for (t,edge) in self.path.iter() {
+            states.push((at,edge.source_col));
+            states.push((*t,edge.dest_col));
+            at = *t;
+        } states
+    }
+    fn satisfied_targets(&self,targets:&Targets)->Vec<(usize,Expr)> {
+        let mut satisfied:Vec<(usize,Expr)> = Vec::new();
+        for (options,avail) in targets.iter().zip(self.available_targets.iter()) {
+            for ((_,t,expr),_) in options.iter().zip(avail.iter()).filter(|(_,a)|a.is_some()) {
+                if !satisfied.iter().any(|(t2,e2)|t2==t && e2==expr) {satisfied.push((*t,expr.clone()));}
+            }
+        } satisfied
+    }
+    fn covers_output(&self)->bool {
+        self.available_targets.iter().all(|x|x.iter().any(|y|y.is_some()))
+    }
+}
+//Targets are marked one output column at a time, so a path may satisfy every column through different rows of the join
+//without one row satisfying them all; the candidates built from it are evaluated on the examples, which rejects those.
+fn mark_available_targets(avail:&mut [Box<[Option<usize>]>],formatch:&RowMapping,table:usize,position:usize,targets:&Targets) {
+    for i in 0..targets.len() {
+        for j in 0..targets[i].len() {
+            let (rowmap,ttable,_) = &targets[i][j];
+            if avail[i][j].is_some() || *ttable!=table {continue;}
+            if formatch.iter().zip(rowmap.iter()).all(|((reached,_),(wanted,isnull))|*isnull || reached.iter().any(|x|wanted.contains(x))) {
+                avail[i][j] = Some(position);
+            }
+        }
+    }
 }
 #[derive(Clone)]
 struct LinkGraphEdge {
     source_col:usize,
     dest_col:usize,
-    forward_pairing: Rc<Pairing>,
-    backward_pairing: Rc<Pairing>
+    forward_pairing: Arc<Pairing>,
+    backward_pairing: Arc<Pairing>
 }
 type LinkGraph = Graph<usize,LinkGraphEdge,Directed>;
+//Walks the link graph outward from a starting path, yielding every join chain (up to max_depth joins) that keeps
+//all output rows reachable, along with the (table, column) targets satisfied somewhere along it.
+//A chain never re-enters a (table, column) state it has already joined through.
 struct BreadthFirstExpand<'a> {
-    queue: VecDeque<(NodeIndex,(Option<(EdgeIndex,usize)>,PathAssociated))>,
-    graph: LinkGraph,
-    next: Option<(IntoIter<LifeTimeLessEdgeRef>,(Option<(EdgeIndex,usize)>,PathAssociated))>,
-    targets: &'a Vec<Vec<(RowMapping,usize,usize)>>//table, column
+    queue: VecDeque<(NodeIndex,PathAssociated)>,
+    graph: &'a LinkGraph,
+    next: Option<(IntoIter<LifeTimeLessEdgeRef>,PathAssociated)>,
+    targets: &'a Targets,
+    start: Option<PathAssociated>,
+    max_depth: usize
+}
+impl<'a> BreadthFirstExpand<'a> {
+    fn new(graph:&'a LinkGraph,targets:&'a Targets,start:PathAssociated,max_depth:usize)->Self {
+        BreadthFirstExpand {
+            queue:iter::once((NodeIndex::new(start.root),start.clone())).collect(),
+            graph,
+            next:None,
+            targets,
+            start:Some(start),
+            max_depth
+        }
+    }
+    fn item(&self,path:PathAssociated)->(PathAssociated,Vec<(usize,Expr)>) {
+        let satisfied = path.satisfied_targets(self.targets);
+        (path,satisfied)
+    }
 }
 impl<'a> Iterator for BreadthFirstExpand<'a> {
-    type Item = (PathAssociated,usize,usize);//target, column
+    type Item = (PathAssociated,Vec<(usize,Expr)>);//path, satisfied (table, column expression) targets
     fn next(&mut self) -> Option<Self::Item>  {
+        if let Some(start) = self.start.take() {return Some(self.item(start));}
         loop {
             match &mut self.next {
                 None=>match self.queue.pop_front(){
                     None=>{return None;}
                     Some((nx,prev))=>{
-                        let a:Vec<_> = self.graph.edges(nx).map(|x|remove_lifetime(x)).collect();
+                        if prev.path.len()>=self.max_depth {continue;}
+                        let a:Vec<_> = self.graph.edges(nx).map(remove_lifetime).collect();
                         self.next=Some((a.into_iter(),prev));
                         continue;
                     }
@@ -461,14 +2065,15 @@ impl<'a> Iterator for BreadthFirstExpand<'a> {
                 Some((x,prev))=>match x.next() {
                     None => {self.next=None;continue;}
                     Some(y) => {
-                        if Some((y.id,y.weight.source_col)) == prev.0 {continue;}
-                        if let Some(newassociatedpath) = prev.1.concat_path_associated(
+                        let totable = self.graph[y.target];
+                        if prev.visited().contains(&(totable,y.weight.dest_col)) {continue;}
+                        if let Some(newassociatedpath) = prev.concat_path_associated(
                             &y.weight,
-                            self.graph[y.target],
+                            totable,
                             self.targets
                         ) {
-                            self.queue.push_back((y.target,(Some((y.id,y.weight.dest_col)),newassociatedpath.clone())));
-                            return Some((newassociatedpath,555,555));
+                            self.queue.push_back((y.target,newassociatedpath.clone()));
+                            return Some(self.item(newassociatedpath));
                         }
                     }
                 }
@@ -477,10 +2082,6 @@ impl<'a> Iterator for BreadthFirstExpand<'a> {
     }
 }
 
-// struct CartesianPathIterator<'a> {
-//     bfe:BreadthFirstExpand<'a>
-//     results:Vec<Vec<>>
-// }
 
 
 
@@ -488,15 +2089,10 @@ impl<'a> Iterator for BreadthFirstExpand<'a> {
 
 
 
-#[derive(Clone,Copy)]
-enum RowMappingQuality {
-    EqualFooting,
-    LeftMoreEmpty,
-    RightMoreEmpty
-}
 type RowMapping = Vec<(Vec<usize>,bool)>;
+type Targets = Vec<Vec<(RowMapping,usize,Expr)>>;//for each output column: row mapping, table, expression over that table's columns
 
-fn extract_comparisons(tables:&Vec<Table>)->LinkGraph {
+fn extract_comparisons(tables:&[Table])->LinkGraph {
     let mut deps = LinkGraph::new();
     for ind in 0..tables.len() {deps.add_node(ind);}
     for ind1 in deps.node_indices() {
@@ -508,13 +2104,11 @@ fn extract_comparisons(tables:&Vec<Table>)->LinkGraph {
                 for (icol2,col2) in tab2.columns.iter().enumerate() {
                     if icol1==icol2 && ind1==ind2 {continue;}
                     if let Some((forward,backward)) = match columns_same_type(col1,col2) {
-                        Some(ColumnPair::Numeric(a,b))=>create_bi_pairing(a,b),
-                        Some(ColumnPair::String(a,b))=>create_bi_pairing(a,b),
-                        Some(ColumnPair::Time(a,b))=>create_bi_pairing(a,b),
-                        None=>None
+                        Some(ColumnPair::Boolean(..))|None=>None,//two values make no join key
+                        Some(pair)=>with_pair!(pair,a,b=>create_bi_pairing(a,b,&EXACT))//joins evaluate with exact equality
                     } {
-                        let forl = Rc::new(forward);
-                        let backl = Rc::new(backward);
+                        let forl = Arc::new(forward);
+                        let backl = Arc::new(backward);
                         deps.add_edge(ind1,ind2,LinkGraphEdge {
                             source_col:icol1,dest_col:icol2,
                             forward_pairing:forl.clone(),
@@ -531,35 +2125,35 @@ fn extract_comparisons(tables:&Vec<Table>)->LinkGraph {
         }
     } deps
 }
-fn create_bi_pairing<'a,T:PartialEq>(target:&'a [Option<T>],source:&'a [Option<T>])->Option<(Vec<Vec<usize>>,Vec<Vec<usize>>)> {
-    let ab = create_pairing(target,source);
-    if ab.iter().all(|x|x.len()==0) {return None}
-    let ba = create_pairing(source,target);
-    return Some((ab,ba));
+fn create_bi_pairing<'a,T:PartialEq+Approx>(target:&'a [Option<T>],source:&'a [Option<T>],tol:&Tolerance)->Option<(Pairing,Pairing)> {
+    let ab = create_pairing(target,source,tol);
+    if ab.iter().all(|x|x.is_empty()) {return None}
+    let ba = create_pairing(source,target,tol);
+    Some((ab,ba))
 }
-fn create_pairing<'a,T:PartialEq>(target:&'a [Option<T>],source:&'a [Option<T>])->Vec<Vec<usize>> {
+fn create_pairing<'a,T:PartialEq+Approx>(target:&'a [Option<T>],source:&'a [Option<T>],tol:&Tolerance)->Pairing {
     let mut outp = Vec::new();
     for t in target {
         let mut rv = Vec::new();
-        if None!=*t {
-            for j in 0..source.len() {
-                if *t==source[j] { rv.push(j); }
+        if t.is_some() {
+            for (j,s) in source.iter().enumerate() {
+                if t.approx_eq(s,tol) { rv.push(j); }
             }
         }
         outp.push(rv);
     } outp
 }
-fn compare_columns<'a,T:PartialEq>(target:&'a [Option<T>],source:&'a [Option<T>])->Option<RowMapping> {
+fn compare_columns<'a,T:PartialEq+Approx>(target:&'a [Option<T>],source:&'a [Option<T>],tol:&Tolerance)->Option<RowMapping> {
     let mut outp = Vec::new();
     for t in target {
         let mut rv = Vec::new();
-        for j in 0..source.len() {
-            if *t==source[j] {
+        for (j,s) in source.iter().enumerate() {
+            if t.approx_eq(s,tol) {
                 rv.push(j);
             }
         }
-        if rv.len()==0 && *t != None {return None}
-        outp.push((rv,*t==None));
+        if rv.is_empty() && t.is_some() {return None}
+        outp.push((rv,t.is_none()));
     } Some(outp)
 }
 fn translate_map_across_pair(map:&RowMapping,pair:&Pairing)->(RowMapping,Vec<usize>) {
@@ -572,11 +2166,13 @@ fn translate_map_across_pair(map:&RowMapping,pair:&Pairing)->(RowMapping,Vec<usi
                 rv.push(*p);
             }
         }
-        if rv.len()==0 && !s {
+        rv.sort_unstable();
+        rv.dedup();
+        if rv.is_empty() && !s {
             dropped.push(ind);
         } else {outp.push((rv,*s));}
     }
-    return (outp,dropped)
+    (outp,dropped)
 }
 fn concatenate_pairings(a:&Pairing,b:&Pairing)->Pairing {
     let mut res = Vec::new();
@@ -590,56 +2186,113 @@ fn concatenate_pairings(a:&Pairing,b:&Pairing)->Pairing {
         res.push(rv);
     } res
 }
-// fn compare_mappings(maps:&Vec<RowMapping>)->Option<(RowMapping,RowMappingQuality)> {
-//     let mut outp = Vec::new();
-//     let mut quality = RowMappingQuality::EqualFooting;
-//     // if a.len()!=b.len() {panic!("Two mappings of different sizes should never arise.");}
-//     for lm in a.iter().zip(b.iter()) {
-//         outp.push(match (lm,quality) {
-//             ((None,None),_) => None,
-//             ((Some(_),None),RowMappingQuality::LeftMoreEmpty) => return None,//Outer joins are not allowed in our DSL for good reason
-//             ((None,Some(_)),RowMappingQuality::RightMoreEmpty) => return None,//Outer joins are not allowed in our DSL for good reason
-//             ((Some(a),None),_) => {
-//                 quality=RowMappingQuality::RightMoreEmpty;Some(a.clone())
-//             },
-//             ((None,Some(b)),_) => {
-//                 quality=RowMappingQuality::LeftMoreEmpty;Some(b.clone())
-//             },
-//             ((Some(a),Some(b)),_) => {
-//                 let mut rv = Vec::new();
-//                 let mut i=0;let mut j=0;
-//                 while i<a.len() && j<b.len() {//each range in a rowmapping is sorted; this block calculates the intersection
-//                     if a[i]==b[j] {
-//                         rv.push(a[i]);
-//                         i+=1;j+=1;
-//                     } else if a[i]<b[i] {i+=1;} else {j+=1}
-//                 }
-//                 if rv.len()==0 {return None}
-//                 Some(rv)
-//             },
-//         });
-//     }
-//     return Some((RowMapping {ranges:outp},quality))
-// }
-
-
-fn all_potential_mappings(inputs:&Vec<Table>,output:&Table)->Vec<Vec<(RowMapping,usize,usize)>> {
+
+
+fn map_column(target:&Column,source:&Column,tol:&Tolerance)->Option<RowMapping> {
+    with_pair!(columns_same_type(target,source)?,a,b=>compare_columns(a,b,tol))
+}
+//output columns no input column explains directly get small expressions over the columns of a single table
+fn all_potential_mappings(inputs:&[Table],output:&Table,tol:&Tolerance)->Targets {
     output.columns.iter().map(|col1|{
         let mut options = Vec::new();
         for (ind2,tab2) in inputs.iter().enumerate() {
             for (icol2,col2) in tab2.columns.iter().enumerate() {
-                if let Some(rowmap) = match columns_same_type(col1,col2) {
-                    Some(ColumnPair::Numeric(a,b))=>compare_columns(a,b),
-                    Some(ColumnPair::String(a,b))=>compare_columns(a,b),
-                    Some(ColumnPair::Time(a,b))=>compare_columns(a,b),
-                    None=>None
-                } {
-                    options.push((rowmap,ind2,icol2))
+                if let Some(rowmap) = map_column(col1,col2,tol) {
+                    options.push((rowmap,ind2,Expr::Col(icol2)))
                 }
             }
+        }
+        if options.is_empty() {
+            for (ind2,tab2) in inputs.iter().enumerate() {
+                options.extend(derived_mappings(tab2,col1,tol).into_iter().map(|(rowmap,expr)|(rowmap,ind2,expr)));
+            }
         } options
     }).collect()
 }
+fn derived_mappings(table:&Table,target:&Column,tol:&Tolerance)->Vec<(RowMapping,Expr)> {
+    let mut options = Vec::new();
+    for expr in small_expressions(table,target) {
+        if let Some(rowmap) = map_column(target,&eval_expr(&expr,table),tol) {
+            options.push((rowmap,expr));
+            continue;
+        }
+        if is_numeric(target) {//the example may show a rounded value
+            let places = into_numeric(target.clone()).iter().flatten().filter_map(|x|decimals(*x)).max().unwrap_or(0);
+            let rounded = Expr::Round(Box::new(expr),places);
+            if let Some(rowmap) = map_column(target,&eval_expr(&rounded,table),tol) {
+                options.push((rowmap,rounded));
+            }
+        }
+    } options
+}
+const EXPRESSION_CONSTANTS:[f64;8] = [2.0,10.0,12.0,24.0,60.0,100.0,1000.0,3600.0];
+const CONCAT_SEPARATORS:[&str;6] = [" ",", ","-","_","/",""];
+fn small_expressions(table:&Table,target:&Column)->Vec<Expr> {
+    let col = |c:&usize|Box::new(Expr::Col(*c));
+    let num = |k:&f64|Box::new(Expr::Num(Num(*k)));
+    let numeric:Vec<usize> = (0..table.columns.len()).filter(|c|is_numeric(&table.columns[*c])).collect();
+    let strings:Vec<usize> = (0..table.columns.len()).filter(|c|matches!(*table.columns[*c],Column::String(_))).collect();
+    let times:Vec<usize> = (0..table.columns.len()).filter(|c|matches!(*table.columns[*c],Column::Time(_))).collect();
+    let parts = [DatePart::Year,DatePart::Month,DatePart::Day,DatePart::Hour];
+    let mut exprs = Vec::new();
+    match target {
+        Column::Numeric(_)|Column::Integer(_)|Column::Decimal(_)=>{
+            for a in numeric.iter() {
+                exprs.push(Expr::Neg(col(a)));
+                for k in EXPRESSION_CONSTANTS.iter() {
+                    for op in [Arith::Add,Arith::Sub,Arith::Mul,Arith::Div] {
+                        exprs.push(Expr::Arith(op,col(a),num(k)));
+                    }
+                }
+                for b in numeric.iter().filter(|b|*b!=a) {
+                    if a<b {
+                        exprs.push(Expr::Arith(Arith::Add,col(a),col(b)));
+                        exprs.push(Expr::Arith(Arith::Mul,col(a),col(b)));
+                    }
+                    exprs.push(Expr::Arith(Arith::Sub,col(a),col(b)));
+                    exprs.push(Expr::Arith(Arith::Div,col(a),col(b)));
+                    for k in EXPRESSION_CONSTANTS.iter() {
+                        for (outer,inner) in [(Arith::Add,Arith::Div),(Arith::Sub,Arith::Div),(Arith::Add,Arith::Mul),(Arith::Sub,Arith::Mul)] {
+                            exprs.push(Expr::Arith(outer,col(a),Box::new(Expr::Arith(inner,col(b),num(k)))));
+                        }
+                    }
+                }
+            }
+            for c in strings.iter() {
+                exprs.push(Expr::Cast(col(c),ColumnSchema::Numeric));
+                exprs.push(Expr::Apply(StringFn::Length,col(c)));
+            }
+            for a in times.iter() {
+                for part in parts.iter() {exprs.push(Expr::Extract(part.clone(),col(a)));}
+                for b in times.iter().filter(|b|*b!=a) {exprs.push(Expr::DateDiff(col(a),col(b)));}
+            }
+        }
+        Column::String(wanted)=>{
+            let lengths:Vec<usize> = wanted.iter().flatten().map(|x|x.chars().count()).collect();
+            for a in strings.iter() {
+                for func in [StringFn::Upper,StringFn::Lower,StringFn::Trim] {exprs.push(Expr::Apply(func,col(a)));}
+                if !lengths.is_empty() && lengths.iter().all(|l|*l==lengths[0]) {exprs.push(Expr::Substr(col(a),1,Some(lengths[0])));}
+                for b in strings.iter().filter(|b|*b!=a) {
+                    for sep in CONCAT_SEPARATORS.iter() {
+                        let parts = if sep.is_empty() {vec![Expr::Col(*a),Expr::Col(*b)]} else {vec![Expr::Col(*a),Expr::Str(String::from(*sep)),Expr::Col(*b)]};
+                        exprs.push(Expr::Concat(parts));
+                    }
+                }
+            }
+            for c in 0..table.columns.len() {
+                if !matches!(*table.columns[c],Column::String(_)) {exprs.push(Expr::Cast(col(&c),ColumnSchema::String));}
+            }
+        }
+        Column::Boolean(_)=>for c in strings.iter() {exprs.push(Expr::Cast(col(c),ColumnSchema::Boolean));},
+        Column::Time(_)=>for a in times.iter() {
+            for part in parts.iter() {
+                exprs.push(Expr::Trunc(part.clone(),col(a)));
+                for amount in [1,-1] {exprs.push(Expr::Interval(col(a),amount,part.clone()));}
+            }
+        }
+    }
+    exprs
+}
 
 
 
@@ -649,21 +2302,35 @@ fn all_potential_mappings(inputs:&Vec<Table>,output:&Table)->Vec<Vec<(RowMapping
 
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 enum ColumnSchema {
     String,
     Numeric,
-    Time(String)
+    Time(String),
+    Integer,
+    Boolean,
+    Decimal
 }
 #[derive(Debug, Serialize, Deserialize)]
 struct TableSchema {
     name:String,
     columns:Vec<(String,ColumnSchema)>
 }
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+enum Comparison {
+    #[default]
+    Ordered,//rows must appear in the same order
+    Unordered,//same rows with the same multiplicities, any order
+    Set//same distinct rows
+}
 #[derive(Debug, Serialize, Deserialize)]
 struct TestCaseSchema {
     inputs:Vec<TableSchema>,
-    output:Vec<(String,ColumnSchema)>
+    output:Vec<(String,ColumnSchema)>,
+    #[serde(default)]
+    comparison:Comparison,
+    #[serde(default)]
+    tolerance:Tolerance
 }
 
 #[derive(Debug)]
@@ -678,11 +2345,733 @@ struct Example {
 
 
 
-fn fit_examples(_schema:&TestCaseSchema,examples: &Vec<Example>)->Tier1Table {
-    for example in examples {
-        let graph = extract_comparisons(&example.inputs);
+const SYNTH_MAX_JOINS:usize = 3;
+const SYNTH_MAX_ASSIGNMENTS:usize = 64;
+
+//the join chain along a path, and the offset of each table's columns within it
+fn join_tree(inputs:&[Table],root:usize,path:&[(usize,LinkGraphEdge)])->(Tier4Table,Vec<usize>) {
+    let mut offsets = vec![0,inputs[root].columns.len()];
+    let mut tree = Named(root);
+    for (ind,(t,edge)) in path.iter().enumerate() {
+        tree = Join(Box::new(tree),Box::new(Named(*t)),vec![(offsets[ind]+edge.source_col,edge.dest_col)]);
+        offsets.push(offsets.last().unwrap()+inputs[*t].columns.len());
+    }
+    (tree,offsets)
+}
+//one pick from every list of choices, at most SYNTH_MAX_ASSIGNMENTS of them
+fn assignments<T:Clone>(choices:&[Vec<T>])->Vec<Vec<T>> {
+    let mut res:Vec<Vec<T>> = vec![vec![]];
+    for options in choices.iter() {
+        res = res.iter().flat_map(|a|options.iter().map(move |c|{
+            let mut b = a.clone();b.push(c.clone());b
+        })).take(SYNTH_MAX_ASSIGNMENTS).collect();
+    } res
+}
+fn join_candidates(inputs:&[Table],targets:&Targets,path:&PathAssociated)->Vec<Tier1Table> {
+    let (tree,offsets) = join_tree(inputs,path.root,&path.path);
+    let choices:Vec<Vec<Expr>> = targets.iter().enumerate().map(|(i,options)|{
+        options.iter().enumerate().filter_map(|(j,(_,_,expr))|
+            path.available_targets[i][j].map(|pos|expr.shift(offsets[pos]))
+        ).collect()
+    }).collect();
+    let width = *offsets.last().unwrap();
+    assignments(&choices).into_iter().map(|cols|{
+        if cols.iter().map(|x|x.column()).eq((0..width).map(Some)) {tree.clone().totop()}
+        else {Tier2Table::project(Tier3Table::N(tree.clone()),cols).totop()}
+    }).collect()
+}
+fn fits_examples(candidate:&Tier1Table,examples:&[Example],caches:&mut [EvalCache],mode:Comparison,tol:&Tolerance)->bool {
+    examples.iter().zip(caches.iter_mut()).all(|(example,cache)|{
+        compare_tables(&candidate.evaluate_cached(cache),&example.output,mode,tol)
+    })
+}
+//the first key has to be monotone in every expected output
+fn candidate_orderings(examples:&[Example])->Vec<Vec<Key>> {
+    let width = examples[0].output.columns.len();
+    let monotone = |key:&Key|examples.iter().all(|e|(1..e.output.rows).all(|r|key.compare(&e.output,r-1,r)!=Ordering::Greater));
+    let keys:Vec<Key> = (0..width).flat_map(|c|{
+        let mut keys = vec![Key::asc(c),Key::desc(c)];
+        if examples.iter().any(|e|(0..e.output.rows).any(|r|value_is_null(&e.output,c,r))) {
+            keys.extend([Key::Asc(c,Nulls::First),Key::Desc(c,Nulls::Last)]);
+        } keys
+    }).collect();
+    let mut orderings:Vec<Vec<Key>> = keys.iter().filter(|k|monotone(k)).map(|k|vec![k.clone()]).collect();
+    let mut pairs:Vec<Vec<Key>> = Vec::new();
+    for o in orderings.iter() {
+        for k in keys.iter() {
+            if o[0].column()!=k.column() {pairs.push(vec![o[0].clone(),k.clone()]);}
+        }
+    }
+    orderings.extend(pairs);
+    orderings
+}
+//only called once a candidate already produces the right rows
+fn synthesize_order(candidate:&Tier2Table,examples:&[Example],caches:&mut [EvalCache],tol:&Tolerance)->Option<Tier1Table> {
+    for keys in candidate_orderings(examples) {
+        let ordered = Tier1Table::Order(candidate.clone(),keys);
+        if fits_examples(&ordered,examples,caches,Comparison::Ordered,tol) {return Some(ordered);}
+    }
+    None
+}
+//which rows of a result also appear in the expected output, or None if some expected row is missing
+fn rows_kept(actual:&Table,expected:&Table,tol:&Tolerance)->Option<Vec<bool>> {
+    let compare = row_comparator(actual,expected,tol);
+    let mut used = vec![false;expected.rows];
+    let kept:Vec<bool> = (0..actual.rows).map(|r|{
+        let found = (0..expected.rows).find(|e|!used[*e] && compare(r,*e)==Ordering::Equal);
+        if let Some(e) = found {used[e] = true;}
+        found.is_some()
+    }).collect();
+    if used.iter().all(|x|*x) {Some(kept)} else {None}
+}
+fn common_prefix(values:&[&str])->String {
+    let first:Vec<char> = values[0].chars().collect();
+    let len = values.iter().map(|v|v.chars().zip(first.iter()).take_while(|(a,b)|a==*b).count()).min().unwrap_or(0);
+    first[..len].iter().collect()
+}
+fn like_escaped(a:&str)->bool {
+    !a.contains('%') && !a.contains('_')
+}
+//candidates produce a superset of the expected rows; the conditions are read off the rows that have to stay
+fn filter_predicates(joined:&Table,kept:&[bool])->Vec<Prim> {
+    let mut preds = Vec::new();
+    for c in 0..joined.columns.len() {
+        let dropped_null = kept.iter().enumerate().any(|(r,k)|!*k && value_is_null(joined,c,r));
+        let kept_null = kept.iter().enumerate().any(|(r,k)|*k && value_is_null(joined,c,r));
+        if dropped_null && !kept_null {preds.push(Prim::IsNotNull(c));}
+        if kept_null {continue;}
+        match &*joined.columns[c] {
+            Column::String(v)=>{
+                let values:Vec<&str> = v.iter().zip(kept).filter(|(_,k)|**k).filter_map(|(x,_)|x.as_deref()).collect();
+                if values.is_empty() {continue;}
+                if values.iter().all(|x|*x==values[0]) {preds.push(Prim::Cmp(Expr::Col(c),Binop::Eq,Expr::Str(values[0].to_string())));}
+                let prefix = common_prefix(&values);
+                if !prefix.is_empty() && like_escaped(&prefix) {preds.push(Prim::Like(Expr::Col(c),prefix+"%"));}
+                let reversed:Vec<String> = values.iter().map(|x|x.chars().rev().collect()).collect();
+                let suffix:String = common_prefix(&reversed.iter().map(|x|x.as_str()).collect::<Vec<_>>()).chars().rev().collect();
+                if !suffix.is_empty() && like_escaped(&suffix) {preds.push(Prim::Like(Expr::Col(c),String::from("%")+&suffix));}
+            }
+            Column::Boolean(v)=>{
+                let values:Vec<bool> = v.iter().zip(kept).filter(|(_,k)|**k).filter_map(|(x,_)|*x).collect();
+                if !values.is_empty() && values.iter().all(|x|*x==values[0]) {preds.push(Prim::Cmp(Expr::Col(c),Binop::Eq,Expr::Bool(values[0])));}
+            }
+            column if is_numeric(column)=>{
+                let v = into_numeric(column.clone());
+                let values:Vec<f64> = v.iter().zip(kept).filter(|(_,k)|**k).filter_map(|(x,_)|*x).collect();
+                if values.is_empty() {continue;}
+                let min = values.iter().cloned().fold(f64::INFINITY,f64::min);
+                let max = values.iter().cloned().fold(f64::NEG_INFINITY,f64::max);
+                preds.push(Prim::Cmp(Expr::Col(c),Binop::Gteq,Expr::Num(Num(min))));
+                preds.push(Prim::Cmp(Expr::Col(c),Binop::Lteq,Expr::Num(Num(max))));
+            }
+            Column::Time(v)=>{
+                let values:Vec<NaiveDateTime> = v.iter().zip(kept).filter(|(_,k)|**k).filter_map(|(x,_)|*x).collect();
+                if let (Some(min),Some(max)) = (values.iter().min(),values.iter().max()) {
+                    preds.push(Prim::Cmp(Expr::Col(c),Binop::Gteq,Expr::Time(*min)));
+                    preds.push(Prim::Cmp(Expr::Col(c),Binop::Lteq,Expr::Time(*max)));
+                }
+            }
+            _=>{}
+        }
     }
-    return Named(1).totop()
+    preds
+}
+//semi- and anti-joins on every input column sharing values with a column here, and comparisons with an aggregate of the table itself
+fn subquery_predicates(tree:&Tier4Table,joined:&Table,inputs:&[Table])->Vec<Prim> {
+    let mut preds = Vec::new();
+    for (u,table) in inputs.iter().enumerate() {
+        for c in 0..joined.columns.len() {
+            for d in 0..table.columns.len() {
+                if matches!(*table.columns[d],Column::Time(_)) || !(0..joined.rows).any(|r|!value_is_null(joined,c,r) && value_in_column(joined,c,r,table,d,&EXACT)) {continue;}
+                let sub = Box::new(Named(u).totop());
+                preds.push(Prim::Exists(sub.clone(),vec![(c,d)]));
+                preds.push(Prim::NotExists(sub,vec![(c,d)]));
+            }
+        }
+    }
+    if let Named(t) = tree {
+        let width = inputs[*t].columns.len();
+        for c in (0..width).filter(|c|is_numeric(&inputs[*t].columns[*c])) {
+            let aggregate = |ag:Agg,col:usize|{
+                let whole = Group(Box::new(Tier3Table::N(Named(*t))),vec![],vec![(ag,c)]);
+                Box::new(Tier2Table::project(Tier3Table::N(whole),vec![Expr::Col(col)]).totop())
+            };
+            for op in [Binop::Gt,Binop::Lt] {preds.push(Prim::CmpSubquery(Expr::Col(c),op,aggregate(Agg::Avg,width),vec![]));}
+            for ag in [Agg::Max,Agg::Min] {preds.push(Prim::CmpSubquery(Expr::Col(c),Binop::Eq,aggregate(ag,c),vec![]));}
+        }
+    }
+    preds
+}
+fn synthesize_filter(candidate:&Tier1Table,examples:&[Example],caches:&mut [EvalCache],tol:&Tolerance)->Vec<Tier1Table> {
+    let (tree,cols) = match candidate {
+        Tier1Table::N(Tier2Table::Project(Tier3Table::N(tree),cols))=>(tree,Some(cols)),
+        Tier1Table::N(Tier2Table::N(Tier3Table::N(tree)))=>(tree,None),
+        _=>return Vec::new()
+    };
+    let joined = tree.evaluate_cached(&mut caches[0]);
+    let projected = match cols {Some(cols)=>eval_project(&joined,cols).0,None=>(*joined).clone()};
+    let output = &examples[0].output;
+    let kept = match rows_kept(&projected,output,tol) {
+        Some(kept) if kept.iter().any(|k|!*k)=>kept,
+        _=>return Vec::new()
+    };
+    //a filter has to keep as many rows as expected, all of them ones the output has
+    let wanted = kept.iter().filter(|k|**k).count();
+    let compare = row_comparator(&projected,output,tol);
+    let matchable:Vec<bool> = (0..projected.rows).map(|r|(0..output.rows).any(|e|compare(r,e)==Ordering::Equal)).collect();
+    let mut preds = filter_predicates(&joined,&kept);
+    preds.extend(subquery_predicates(tree,&joined,&examples[0].inputs));
+    preds.into_iter().filter(|prim|{
+        let subqueries:Subqueries = prim.subquery().map(|x|(x,x.evaluate_cached(&mut caches[0]))).into_iter().collect();
+        let mask = eval_pred(&vec![prim.clone()],&joined,&subqueries);
+        mask.iter().filter(|m|**m).count()==wanted && mask.iter().zip(matchable.iter()).all(|(m,x)|!*m || *x)
+    }).map(|prim|{
+        let selected = Tier3Table::Select(tree.clone(),vec![prim]);
+        match cols {
+            Some(cols)=>Tier2Table::Project(selected,cols.clone()).totop(),
+            None=>Tier2Table::N(selected).totop()
+        }
+    }).collect()
+}
+//Columns bucketing a few labels get a constant stand-in so a join path can cover them; the decision list
+//choosing the label is learned once the other columns line the candidate's rows up with the expected ones.
+const CASE_MAX_LABELS:usize = 4;
+fn case_placeholders(inputs:&[Table],output:&Table,targets:&mut Targets) {
+    for (j,options) in targets.iter_mut().enumerate() {
+        if !options.is_empty() || (0..output.rows).any(|r|value_is_null(output,j,r)) {continue;}
+        let labels = case_labels(output,j);
+        if labels.is_empty() || labels.iter().any(|x|*x>=CASE_MAX_LABELS) {continue;}
+        let constant = match &*output.columns[j] {
+            Column::String(v)=>Expr::Str(v[0].as_ref().unwrap().to_string()),
+            Column::Boolean(v)=>Expr::Bool(v[0].unwrap()),
+            Column::Time(_)=>continue,
+            column=>Expr::Num(Num(into_numeric(column.clone())[0].unwrap()))
+        };
+        for (t,table) in inputs.iter().enumerate() {
+            let rowmap = (0..output.rows).map(|_|((0..table.rows).collect(),false)).collect();
+            options.push((rowmap,t,Expr::Case(Vec::new(),Box::new(constant.clone()))));
+        }
+    }
+}
+//the label of every row, numbered in order of first appearance
+fn case_labels(output:&Table,col:usize)->Vec<usize> {
+    let mut firsts:Vec<usize> = Vec::new();
+    (0..output.rows).map(|r|{
+        match firsts.iter().position(|f|compare_table_values(output,col,*f,output,col,r)) {
+            Some(l)=>l,
+            None=>{firsts.push(r);firsts.len()-1}
+        }
+    }).collect()
+}
+fn is_placeholder(expr:&Expr)->bool {
+    matches!(expr,Expr::Case(branches,_) if branches.is_empty())
+}
+//the roundest number in (lo,hi]
+fn round_between(lo:f64,hi:f64)->f64 {
+    for e in (-6..=6).rev() {
+        let scale = 10f64.powi(e);
+        let x = round_to(((lo/scale).floor()+1.0)*scale,(-e).max(0));
+        if x>lo && x<=hi {return x;}
+    } hi
+}
+//conditions that hold on every row in pos, tried alone and in pairs until one also fails on every row in neg
+fn separating_condition(joined:&Table,pos:&[usize],neg:&[usize])->Option<Pred> {
+    let mut covering:Vec<Prim> = Vec::new();
+    for c in 0..joined.columns.len() {
+        if pos.iter().all(|r|value_is_null(joined,c,*r)) {covering.push(Prim::IsNull(c));continue;}
+        if pos.iter().any(|r|value_is_null(joined,c,*r)) {continue;}
+        if neg.iter().any(|r|value_is_null(joined,c,*r)) {covering.push(Prim::IsNotNull(c));}
+        match &*joined.columns[c] {
+            Column::Boolean(v) if pos.iter().all(|r|v[*r]==v[pos[0]])=>{
+                covering.push(Prim::Cmp(Expr::Col(c),Binop::Eq,Expr::Bool(v[pos[0]].unwrap())));
+            }
+            column if is_numeric(column)=>{
+                let v = into_numeric(column.clone());
+                let pv:Vec<f64> = pos.iter().filter_map(|r|v[*r]).collect();
+                let nv:Vec<f64> = neg.iter().filter_map(|r|v[*r]).collect();
+                let lo = pv.iter().cloned().fold(f64::INFINITY,f64::min);
+                let hi = pv.iter().cloned().fold(f64::NEG_INFINITY,f64::max);
+                if lo==hi {covering.push(Prim::Cmp(Expr::Col(c),Binop::Eq,Expr::Num(Num(lo))));}
+                if let Some(below) = nv.iter().cloned().filter(|x|*x<lo).reduce(f64::max) {
+                    covering.push(Prim::Cmp(Expr::Col(c),Binop::Gteq,Expr::Num(Num(round_between(below,lo)))));
+                }
+                if let Some(above) = nv.iter().cloned().filter(|x|*x>hi).reduce(f64::min) {
+                    covering.push(Prim::Cmp(Expr::Col(c),Binop::Lt,Expr::Num(Num(round_between(hi,above)))));
+                }
+            }
+            Column::String(v) if pos.iter().all(|r|v[*r]==v[pos[0]])=>{
+                covering.push(Prim::Cmp(Expr::Col(c),Binop::Eq,Expr::Str(v[pos[0]].as_ref().unwrap().to_string())));
+            }
+            _=>{}
+        }
+    }
+    let masks:Vec<Vec<bool>> = covering.iter().map(|p|eval_pred(&vec![p.clone()],joined,&Subqueries::new())).collect();
+    let separates = |ms:&[&Vec<bool>]|neg.iter().all(|r|!ms.iter().all(|m|m[*r]));
+    for (i,m) in masks.iter().enumerate() {
+        if separates(&[m]) {return Some(vec![covering[i].clone()]);}
+    }
+    for i in 0..masks.len() {
+        for j in i+1..masks.len() {
+            if separates(&[&masks[i],&masks[j]]) {return Some(vec![covering[i].clone(),covering[j].clone()]);}
+        }
+    }
+    None
+}
+//peels off the rarest label that a condition can separate from the rest, leaving the commonest for ELSE
+fn decision_list(joined:&Table,desired:&[Option<usize>],labels:&[Expr])->Option<Expr> {
+    let mut remaining:Vec<usize> = (0..joined.rows).filter(|r|desired[*r].is_some()).collect();
+    let mut branches = Vec::new();
+    loop {
+        let mut counts = vec![0;labels.len()];
+        for r in remaining.iter() {counts[desired[*r].unwrap()]+=1;}
+        let mut present:Vec<usize> = (0..labels.len()).filter(|l|counts[*l]!=0).collect();
+        if present.len()<=1 {
+            let otherwise = present.first().map_or(labels[0].clone(),|l|labels[*l].clone());
+            return Some(Expr::Case(branches,Box::new(otherwise)));
+        }
+        present.sort_by_key(|l|counts[*l]);
+        let (label,cond) = present.iter().find_map(|l|{
+            let (pos,neg):(Vec<usize>,Vec<usize>) = remaining.iter().partition(|r|desired[**r]==Some(*l));
+            separating_condition(joined,&pos,&neg).map(|cond|(*l,cond))
+        })?;
+        let mask = eval_pred(&cond,joined,&Subqueries::new());
+        remaining.retain(|r|!mask[*r]);
+        branches.push((cond,labels[label].clone()));
+    }
+}
+fn synthesize_case(candidate:&Tier1Table,examples:&[Example],caches:&mut [EvalCache],tol:&Tolerance)->Option<Tier1Table> {
+    let (tree,projs) = match candidate {
+        Tier1Table::N(Tier2Table::Project(Tier3Table::N(tree),projs))=>(tree,projs),
+        _=>return None
+    };
+    let cols:Vec<Expr> = projs.iter().map(|(x,_)|x.clone()).collect();
+    if !cols.iter().any(is_placeholder) {return None;}
+    let output = &examples[0].output;
+    let joined = tree.evaluate_cached(&mut caches[0]);
+    let projected = eval_project(&joined,projs).0;
+    let fixed:Vec<usize> = (0..cols.len()).filter(|j|!is_placeholder(&cols[*j])).collect();
+    let matches:Vec<Vec<usize>> = (0..projected.rows).map(|r|(0..output.rows).filter(|e|{
+        fixed.iter().all(|j|compare_table_values_tol(&projected,*j,r,output,*j,*e,tol))
+    }).collect()).collect();
+    let mut learned = cols.clone();
+    for j in (0..cols.len()).filter(|j|is_placeholder(&cols[*j])) {
+        let labels = case_labels(output,j);
+        let mut firsts:Vec<usize> = Vec::new();
+        for (r,l) in labels.iter().enumerate() {if *l==firsts.len() {firsts.push(r);}}
+        let values:Vec<Expr> = firsts.iter().map(|r|match &*output.columns[j] {
+            Column::String(v)=>Expr::Str(v[*r].as_ref().unwrap().to_string()),
+            Column::Boolean(v)=>Expr::Bool(v[*r].unwrap()),
+            Column::Time(_)=>unreachable!(),
+            column=>Expr::Num(Num(into_numeric(column.clone())[*r].unwrap()))
+        }).collect();
+        let desired:Vec<Option<usize>> = matches.iter().map(|m|{//rows that match nothing or disagree don't constrain the list
+            let first = labels[*m.first()?];
+            if m.iter().all(|e|labels[*e]==first) {Some(first)} else {None}
+        }).collect();
+        learned[j] = decision_list(&joined,&desired,&values)?;
+    }
+    let found = Tier2Table::project(Tier3Table::N(tree.clone()),learned).totop();
+    if fits_examples(&found,examples,caches,Comparison::Unordered,tol) {Some(found)} else {None}
+}
+//a candidate with duplicate rows
+fn synthesize_distinct(candidate:&Tier1Table,caches:&mut [EvalCache])->Option<Tier1Table> {
+    let (sq,cols) = match candidate {
+        Tier1Table::N(Tier2Table::Project(sq,cols))=>(sq,cols.clone()),
+        Tier1Table::N(Tier2Table::N(sq))=>(sq,(0..sq.evaluate_cached(&mut caches[0]).columns.len()).map(|c|(Expr::Col(c),None)).collect()),
+        _=>return None
+    };
+    Some(Tier2Table::Distinct(sq.clone(),cols).totop())
+}
+//a candidate with too many rows, of which the expected ones are a run in some order; the count comes from the first example
+fn synthesize_limit(candidate:&Tier1Table,schema:&TestCaseSchema,examples:&[Example],caches:&mut [EvalCache])->Option<Tier1Table> {
+    let tol = &schema.tolerance;
+    let unordered = match candidate {Tier1Table::N(x)=>x,_=>return None};
+    let output = &examples[0].output;
+    let full = unordered.evaluate_cached(&mut caches[0]);
+    if full.rows<=output.rows || output.rows==0 {return None;}
+    for keys in candidate_orderings(examples) {
+        let (ordered,_) = eval_order(&full,&keys);
+        let mut offsets = vec![0];
+        if let Some(start) = (0..ordered.rows).find(|r|compare_rows(&ordered,*r,output,0,tol)==Ordering::Equal) {
+            if start!=0 {offsets.push(start);}
+        }
+        for offset in offsets {
+            let limited = Tier1Table::Limit(unordered.clone(),keys.clone(),output.rows,offset);
+            if fits_examples(&limited,examples,caches,schema.comparison,tol) {return Some(limited);}
+        }
+    }
+    None
+}
+//a candidate with the right rows may still need its order
+fn finish_candidate(candidate:&Tier1Table,schema:&TestCaseSchema,examples:&[Example],caches:&mut [EvalCache])->Option<Tier1Table> {
+    if fits_examples(candidate,examples,caches,schema.comparison,&schema.tolerance) {return Some(candidate.clone());}
+    if schema.comparison!=Comparison::Ordered || !fits_examples(candidate,examples,caches,Comparison::Unordered,&schema.tolerance) {return None;}
+    match candidate {
+        Tier1Table::N(unordered)=>synthesize_order(unordered,examples,caches,&schema.tolerance),
+        _=>None
+    }
+}
+//the candidate as is, then with whichever single operator on top of it would fix its rows
+fn refine_candidate(candidate:&Tier1Table,schema:&TestCaseSchema,examples:&[Example],caches:&mut [EvalCache])->Option<Tier1Table> {
+    if let Some(found) = finish_candidate(candidate,schema,examples,caches) {return Some(found);}
+    if let Some(found) = synthesize_case(candidate,examples,caches,&schema.tolerance).and_then(|x|finish_candidate(&x,schema,examples,caches)) {return Some(found);}
+    for filtered in synthesize_filter(candidate,examples,caches,&schema.tolerance) {
+        if let Some(found) = finish_candidate(&filtered,schema,examples,caches) {return Some(found);}
+    }
+    if let Some(found) = synthesize_distinct(candidate,caches).and_then(|x|finish_candidate(&x,schema,examples,caches)) {return Some(found);}
+    synthesize_limit(candidate,schema,examples,caches)
+}
+//Candidates are independent of each other, so with more than one worker they are tried a batch at a time, every worker
+//with caches of its own. The earliest candidate in the batch that succeeds wins, as it would trying them one at a time,
+//so the program found doesn't depend on the number of workers.
+//A batch waits for all its attempts, and a failing one can take far longer than the success before it, so batches
+//start at one candidate and double: an early success costs no speculation, and a later one speculates over at most as
+//many candidates as were tried before it.
+const SEARCH_BATCH_PER_WORKER:usize = 8;
+fn first_found<'a,T:Sync,R:Send>(mut candidates:impl Iterator<Item=T>,workers:&mut [Vec<EvalCache<'a>>],attempt:impl Fn(&T,&mut [EvalCache<'a>])->Option<R>+Sync)->Option<R> {
+    if workers.len()==1 {return candidates.find_map(|x|attempt(&x,&mut workers[0]));}
+    let mut size = 1;
+    loop {
+        let batch:Vec<T> = candidates.by_ref().take(size).collect();
+        if batch.is_empty() {return None;}
+        size = (size*2).min(workers.len()*SEARCH_BATCH_PER_WORKER);
+        if let [only] = batch.as_slice() {//the first worker's caches are the warmest
+            if let Some(res) = attempt(only,&mut workers[0]) {return Some(res);}
+            continue;
+        }
+        let (next,earliest) = (&AtomicUsize::new(0),&AtomicUsize::new(usize::MAX));
+        let (batch,attempt) = (&batch,&attempt);
+        let found:Vec<(usize,R)> = thread::scope(|scope|{
+            let handles:Vec<_> = workers.iter_mut().take(batch.len()).map(|caches|scope.spawn(move || {
+                loop {//each worker takes candidates in order, so none past the earliest success needs trying
+                    let i = next.fetch_add(1,AtomicOrdering::Relaxed);
+                    if i>=batch.len() || i>earliest.load(AtomicOrdering::Relaxed) {return None;}
+                    if let Some(res) = attempt(&batch[i],caches) {
+                        earliest.fetch_min(i,AtomicOrdering::Relaxed);
+                        return Some((i,res));
+                    }
+                }
+            })).collect();
+            handles.into_iter().filter_map(|x|x.join().unwrap()).collect()
+        });
+        if let Some((_,res)) = found.into_iter().min_by_key(|(i,_)|*i) {return Some(res);}
+    }
+}
+fn fit_examples(schema:&TestCaseSchema,examples: &[Example],workers:usize)->Option<Tier1Table> {
+    synthesize_program(schema,examples,workers).map(|x|name_outputs(x,schema))
+}
+fn synthesize_program(schema:&TestCaseSchema,examples: &[Example],workers:usize)->Option<Tier1Table> {
+    let first = examples.first()?;
+    //the workers share the memory one cache would have, so more of them don't use more of it
+    let workers = workers.max(1);
+    let mut workers:Vec<Vec<EvalCache>> = (0..workers).map(|_|examples.iter().map(|e|EvalCache::new(&e.inputs,EVAL_CACHE_CELLS/workers)).collect()).collect();
+    let graph = extract_comparisons(&first.inputs);
+    let mut targets = all_potential_mappings(&first.inputs,&first.output,&schema.tolerance);
+    rank_by_name(schema,&mut targets);
+    case_placeholders(&first.inputs,&first.output,&mut targets);
+    let targets = &targets;
+    let mut roots:Vec<usize> = (0..targets.len()).collect();
+    roots.sort_by_key(|x|targets[*x].len());//columns with few explanations make for fewer dead-end walks
+    let candidates = roots.into_iter()
+        .flat_map(|col|(0..targets[col].len()).map(move |option|(col,option)))
+        .flat_map(|(col,option)|BreadthFirstExpand::new(&graph,targets,PathAssociated::root(&first.inputs,targets,col,option),SYNTH_MAX_JOINS))
+        .filter(|(path,_)|path.covers_output())
+        .flat_map(|(path,_)|join_candidates(&first.inputs,targets,&path));
+    if let Some(found) = first_found(candidates,&mut workers,|candidate,caches|refine_candidate(candidate,schema,examples,caches)) {return Some(found);}
+    synthesize_group(schema,examples,targets,&mut workers[0])
+        .or_else(||synthesize_reshape(schema,examples,&mut workers[0]))
+        .or_else(||synthesize_window(schema,examples,targets,&mut workers))
+        .or_else(||synthesize_sets(schema,examples,&mut workers[0]))
+}
+//A single output column no input explains may be numbered, ranked, summed or shifted along an order within partitions of
+//one input table; every other column has to come straight from that table.
+fn synthesize_window(schema:&TestCaseSchema,examples:&[Example],targets:&Targets,workers:&mut [Vec<EvalCache>])->Option<Tier1Table> {
+    let first = &examples[0];
+    let tol = &schema.tolerance;
+    let unexplained:Vec<usize> = (0..targets.len()).filter(|i|targets[*i].is_empty()).collect();
+    let wanted:Vec<usize> = match unexplained.len() {//small counters often happen to appear in some input column
+        0=>(0..targets.len()).collect(),
+        1=>unexplained,
+        _=>return None
+    };
+    for (wanted,(t,table)) in wanted.into_iter().flat_map(|w|first.inputs.iter().enumerate().map(move |x|(w,x))) {
+        let width = table.columns.len();
+        let choices:Vec<Vec<Expr>> = (0..targets.len()).map(|i|{
+            if i==wanted {return vec![Expr::Col(width)];}
+            targets[i].iter().filter(|(_,ot,expr)|*ot==t && expr.column().is_some()).map(|(_,_,expr)|expr.clone()).collect()
+        }).collect();
+        if choices.iter().any(|x|x.is_empty()) {continue;}
+        let orderable:Vec<usize> = (0..width).filter(|c|!(0..table.rows).all(|r|value_is_null(table,*c,r))).collect();
+        let numeric:Vec<usize> = orderable.iter().cloned().filter(|c|is_numeric(&table.columns[*c])).collect();
+        let mut funcs = vec![WindowFn::RowNumber,WindowFn::Rank];
+        funcs.extend(numeric.iter().map(|c|WindowFn::Sum(*c)));
+        for c in orderable.iter() {
+            if columns_same_type(&table.columns[*c],&first.output.columns[wanted]).is_some() {
+                funcs.push(WindowFn::Lag(*c,1));
+                funcs.push(WindowFn::Lead(*c,1));
+            }
+        }
+        let partitions:Vec<Vec<Cname>> = iter::once(vec![]).chain(orderable.iter().map(|c|vec![*c])).collect();
+        let orders:Vec<Vec<Key>> = orderable.iter().flat_map(|c|vec![vec![Key::asc(*c)],vec![Key::desc(*c)]]).collect();
+        let funcs = &funcs;
+        let windows = partitions.iter().flat_map(|partition|{
+            orders.iter().filter(move |k|!partition.contains(&k[0].column()))
+                .flat_map(move |keys|funcs.iter().map(move |func|Window(Box::new(Tier3Table::N(Named(t))),partition.clone(),keys.clone(),func.clone())))
+        });
+        let found = first_found(windows,workers,|window,caches|{
+            let result = window.evaluate_cached(&mut caches[0]);
+            map_column(&first.output.columns[wanted],&result.columns[width],tol)?;
+            assignments(&choices).into_iter().find_map(|cols|{
+                let candidate = Tier2Table::project(Tier3Table::N(window.clone()),cols).totop();
+                refine_candidate(&candidate,schema,examples,caches)
+            })
+        });
+        if found.is_some() {return found;}
+    }
+    None
+}
+//Input column headers showing up as output values call for an unpivot of those columns, and output headers
+//showing up as values of an input column for a pivot on it. The other output columns come straight from the reshaped table.
+fn synthesize_reshape(schema:&TestCaseSchema,examples:&[Example],caches:&mut [EvalCache])->Option<Tier1Table> {
+    let first = &examples[0];
+    let (output,tol) = (&first.output,&schema.tolerance);
+    let as_text = |a:&LazyColumn|into_strings(cast_column(Column::clone(a),&ColumnSchema::String));
+    let output_text:Vec<Vec<Option<Str>>> = output.columns.iter().map(as_text).collect();
+    let mut reshaped:Vec<Tier4Table> = Vec::new();
+    for (t,table) in first.inputs.iter().enumerate() {
+        let names = &schema.inputs[t].columns;
+        let source = Box::new(Tier3Table::N(Named(t)));
+        let headers:Vec<Cname> = (0..table.columns.len()).filter(|c|output_text.iter().any(|v|v.contains(&Some(Str::from(names[*c].0.as_str()))))).collect();
+        for c in headers.iter() {
+            let values:Vec<(Cname,String)> = headers.iter().filter(|d|std::mem::discriminant(&*table.columns[*c])==std::mem::discriminant(&*table.columns[**d])).map(|d|(*d,names[*d].0.clone())).collect();
+            if values.len()>=2 && values[0].0==*c {reshaped.push(Unpivot(source.clone(),values));}
+        }
+        for key in 0..table.columns.len() {
+            let text = as_text(&table.columns[key]);
+            let labels:Vec<usize> = (0..output.columns.len()).filter(|j|text.contains(&Some(Str::from(schema.output[*j].0.as_str())))).collect();
+            if labels.len()<2 {continue;}
+            for value in (0..table.columns.len()).filter(|v|*v!=key && columns_same_type(&table.columns[*v],&output.columns[labels[0]]).is_some()) {
+                reshaped.push(Pivot(source.clone(),key,value,labels.iter().map(|j|schema.output[*j].0.clone()).collect()));
+            }
+        }
+    }
+    for tree in reshaped {
+        let result = tree.evaluate_cached(&mut caches[0]);
+        let width = result.columns.len();
+        let choices:Vec<Vec<Expr>> = (0..output.columns.len()).map(|j|{
+            if let Pivot(_,_,_,labels) = &tree {
+                if let Some(i) = labels.iter().position(|x|*x==schema.output[j].0) {return vec![Expr::Col(width-labels.len()+i)];}
+            }
+            let mut options:Vec<Expr> = (0..width).filter(|c|map_column(&output.columns[j],&result.columns[*c],tol).is_some()).map(Expr::Col).collect();
+            if matches!(tree,Unpivot(..)) && !matches!(schema.output[j].1,ColumnSchema::String) {//the labels are text
+                let cast = Expr::Cast(Box::new(Expr::Col(width-2)),schema.output[j].1.clone());
+                if map_column(&output.columns[j],&eval_expr(&cast,&result),tol).is_some() {options.push(cast);}
+            }
+            options
+        }).collect();
+        for cols in assignments(&choices) {
+            let candidate = Tier2Table::project(Tier3Table::N(tree.clone()),cols).totop();
+            if let Some(found) = refine_candidate(&candidate,schema,examples,caches) {return Some(found);}
+        }
+    }
+    None
+}
+//How alike an output header and an input column name are, ignoring case and punctuation:
+//the same name, one containing the other, or a shared word.
+fn name_similarity(a:&str,b:&str)->usize {
+    let words = |x:&str|->Vec<String> {x.split(|c:char|!c.is_alphanumeric()).filter(|w|!w.is_empty()).map(|w|w.to_lowercase()).collect()};
+    let (wa,wb) = (words(a),words(b));
+    let (ja,jb) = (wa.concat(),wb.concat());
+    if ja==jb {3}
+    else if !ja.is_empty() && !jb.is_empty() && (ja.contains(&jb) || jb.contains(&ja)) {2}
+    else if wa.iter().any(|w|wb.contains(w)) {1}
+    else {0}
+}
+//columns that explain an output column equally well are tried in order of how alike their names are
+fn rank_by_name(schema:&TestCaseSchema,targets:&mut Targets) {
+    for (options,(header,_)) in targets.iter_mut().zip(schema.output.iter()) {
+        options.sort_by_key(|(_,t,expr)|std::cmp::Reverse(expr.column().map_or(0,|c|name_similarity(header,&schema.inputs[*t].columns[c].0))));
+    }
+}
+//gives the program's output columns the expected names where its SQL would call them something else
+fn name_outputs(program:Tier1Table,schema:&TestCaseSchema)->Tier1Table {
+    let name = |sq:Tier2Table|->Tier2Table {
+        let current:Vec<String> = sq.to_sql(schema,&mut 0).columns.into_iter().map(|(_,x)|x).collect();
+        if current.len()!=schema.output.len() || current.iter().zip(schema.output.iter()).all(|(a,(b,_))|a==b) {return sq;}
+        let alias = |i:usize,x:Expr|{let wanted = &schema.output[i].0; (x,if current[i]==*wanted {None} else {Some(wanted.clone())})};
+        match sq {
+            Tier2Table::Project(sq,cols)=>Tier2Table::Project(sq,cols.into_iter().enumerate().map(|(i,(x,_))|alias(i,x)).collect()),
+            Tier2Table::Distinct(sq,cols)=>Tier2Table::Distinct(sq,cols.into_iter().enumerate().map(|(i,(x,_))|alias(i,x)).collect()),
+            Tier2Table::N(sq)=>Tier2Table::Project(sq,(0..current.len()).map(|i|alias(i,Expr::Col(i))).collect())
+        }
+    };
+    match program {
+        Tier1Table::Order(sq,keys)=>Tier1Table::Order(name(sq),keys),
+        Tier1Table::Limit(sq,keys,count,offset)=>Tier1Table::Limit(name(sq),keys,count,offset),
+        Tier1Table::Set(op,all,l,r)=>Tier1Table::Set(op,all,Box::new(name_outputs(*l,schema)),r),//the left side names a set operation's columns
+        Tier1Table::N(sq)=>Tier1Table::N(name(sq))
+    }
+}
+//a projection of one input table standing in for part of the output, narrowed by a filter if it has extra rows
+fn fit_part(candidate:&Tier1Table,first:&Example,rows:&[usize],caches:&mut [EvalCache],tol:&Tolerance)->Option<Tier1Table> {
+    let part = vec![Example {inputs:first.inputs.clone(),output:table_rows(&first.output,rows),basepath:first.basepath.clone()}];
+    if fits_examples(candidate,&part,&mut caches[..1],Comparison::Unordered,tol) {return Some(candidate.clone());}
+    synthesize_filter(candidate,&part,&mut caches[..1],tol).into_iter().find(|x|fits_examples(x,&part,&mut caches[..1],Comparison::Unordered,tol))
+}
+//Output rows that no single program explains may split between two projections of the inputs.
+//Columns only need to share some values with the output here, since each side holds just part of it.
+fn synthesize_sets(schema:&TestCaseSchema,examples:&[Example],caches:&mut [EvalCache])->Option<Tier1Table> {
+    let first = &examples[0];
+    let (output,tol) = (&first.output,&schema.tolerance);
+    let mut sides:Vec<(Tier1Table,Vec<bool>)> = Vec::new();
+    for (t,table) in first.inputs.iter().enumerate() {
+        let choices:Vec<Vec<Expr>> = (0..output.columns.len()).map(|j|{
+            (0..table.columns.len()).filter(|c|{
+                columns_same_type(&output.columns[j],&table.columns[*c]).is_some() &&
+                (0..output.rows).any(|r|!value_is_null(output,j,r) && value_in_column(output,j,r,table,*c,tol))
+            }).map(Expr::Col).collect()
+        }).collect();
+        for cols in assignments(&choices) {
+            let side = Tier2Table::project(Tier3Table::N(Named(t)),cols).totop();
+            let result = side.evaluate_cached(&mut caches[0]);
+            let compare = row_comparator(&result,output,tol);
+            let covered:Vec<bool> = (0..output.rows).map(|r|(0..result.rows).any(|x|compare(x,r)==Ordering::Equal)).collect();
+            sides.push((side,covered));
+        }
+    }
+    let fits = |found:Tier1Table,caches:&mut [EvalCache]|if fits_examples(&found,examples,caches,schema.comparison,tol) {Some(found)} else {None};
+    for (i,(left,lcovered)) in sides.iter().enumerate() {
+        if lcovered.iter().all(|x|*x) {continue;}
+        for (right,rcovered) in sides.iter().skip(i+1).chain(sides.iter().take(i)) {
+            if rcovered.iter().all(|x|*x) || !lcovered.iter().zip(rcovered).all(|(a,b)|*a||*b) {continue;}
+            let lrows:Vec<usize> = (0..output.rows).filter(|r|lcovered[*r]).collect();
+            let rrows:Vec<usize> = (0..output.rows).filter(|r|!lcovered[*r]).collect();
+            let l = match fit_part(left,first,&lrows,caches,tol) {Some(x)=>x,None=>continue};
+            let r = match fit_part(right,first,&rrows,caches,tol) {Some(x)=>x,None=>continue};
+            for all in [true,false] {
+                if let Some(found) = fits(Tier1Table::Set(SetOp::Union,all,Box::new(l.clone()),Box::new(r.clone())),caches) {return Some(found);}
+            }
+        }
+    }
+    for (left,_) in sides.iter().filter(|(_,c)|c.iter().all(|x|*x)) {
+        for (right,rcovered) in sides.iter().filter(|(x,_)|x!=left) {
+            let op = if rcovered.iter().all(|x|*x) {SetOp::Intersect} else if rcovered.iter().all(|x|!*x) {SetOp::Except} else {continue};
+            for all in [false,true] {
+                if let Some(found) = fits(Tier1Table::Set(op.clone(),all,Box::new(left.clone()),Box::new(right.clone())),caches) {return Some(found);}
+            }
+        }
+    }
+    None
+}
+const SYNTH_AGGREGATES:[Agg;5] = [Agg::Count,Agg::Sum,Agg::Avg,Agg::Max,Agg::Min];
+//Grouping over a single input table. Output columns the table explains become keys and the rest aggregates;
+//an explained column may be an aggregate too, as with the maximum of a column.
+fn synthesize_group(schema:&TestCaseSchema,examples:&[Example],targets:&Targets,caches:&mut [EvalCache])->Option<Tier1Table> {
+    let first = &examples[0];
+    let tol = &schema.tolerance;
+    for (t,table) in first.inputs.iter().enumerate() {
+        let width = table.columns.len();
+        let key_options:Vec<Vec<Expr>> = targets.iter().zip(first.output.columns.iter()).map(|(options,target)|{
+            let direct = options.iter().filter(|(_,ot,_)|*ot==t).map(|(_,_,expr)|expr.clone()).filter(|x|x.column().is_some());
+            direct.chain(derived_mappings(table,target,tol).into_iter().map(|(_,expr)|expr)).collect()
+        }).collect();
+        let unexplained:Vec<usize> = (0..targets.len()).filter(|i|key_options[*i].is_empty()).collect();
+        let mut partitions = vec![unexplained.clone()];
+        for i in (0..targets.len()).filter(|i|!key_options[*i].is_empty()) {
+            let mut p = unexplained.clone();p.push(i);p.sort();
+            partitions.push(p);
+        }
+        for aggregated in partitions.into_iter().filter(|p|!p.is_empty()) {
+            let key_cols:Vec<usize> = (0..targets.len()).filter(|i|!aggregated.contains(i)).collect();
+            let key_choices:Vec<Vec<Expr>> = key_cols.iter().map(|i|key_options[*i].clone()).collect();
+            for chosen in assignments(&key_choices) {
+                let mut keys:Vec<Expr> = Vec::new();
+                for k in chosen.iter() {if !keys.contains(k) {keys.push(k.clone());}}
+                let source = Box::new(Tier3Table::N(Named(t)));
+                let groups = Group(source.clone(),keys.clone(),vec![]).evaluate_cached(&mut caches[0]).rows;
+                if groups<first.output.rows {continue;}
+                let appended_keys:Vec<&Expr> = keys.iter().filter(|x|x.column().is_none()).collect();
+                let position = |k:&Expr|k.column().unwrap_or_else(||width+appended_keys.iter().position(|x|*x==k).unwrap());
+                let base = width+appended_keys.len();
+                let agg_choices:Vec<Vec<Gc>> = aggregated.iter().map(|i|{
+                    let mut viable = Vec::new();
+                    for ag in SYNTH_AGGREGATES.iter() {
+                        for c in 0..width {
+                            if matches!(ag,Agg::Sum|Agg::Avg) && !is_numeric(&table.columns[c]) {continue;}
+                            let grouped = Group(source.clone(),keys.clone(),vec![(ag.clone(),c)]).evaluate_cached(&mut caches[0]);
+                            let pos = if ag.in_place() {c} else {base};
+                            if map_column(&first.output.columns[*i],&grouped.columns[pos],tol).is_some() {
+                                viable.push((ag.clone(),c));
+                                if *ag==Agg::Count {break;}//counting any column without nulls is the same
+                            }
+                        }
+                    } viable
+                }).collect();
+                for aggs in assignments(&agg_choices) {
+                    let mut cols:Vec<Expr> = Vec::new();
+                    let (mut next_key,mut next_agg) = (chosen.iter(),aggs.iter());
+                    let mut appended = 0;
+                    for i in 0..targets.len() {
+                        if aggregated.contains(&i) {
+                            let (ag,c) = next_agg.next().unwrap();
+                            cols.push(Expr::Col(if ag.in_place() {*c} else {appended+=1;base+appended-1}));
+                        } else {
+                            cols.push(Expr::Col(position(next_key.next().unwrap())));
+                        }
+                    }
+                    if groups>first.output.rows {
+                        if let Some(found) = synthesize_having(t,&keys,aggs,cols,schema,examples,caches) {return Some(found);}
+                        continue;
+                    }
+                    let candidate = Tier2Table::project(Tier3Table::N(Group(source.clone(),keys.clone(),aggs)),cols).totop();
+                    if let Some(found) = finish_candidate(&candidate,schema,examples,caches) {return Some(found);}
+                }
+            }
+        }
+    }
+    None
+}
+//With more groups than output rows, some groups are dropped by a threshold on an aggregate, which need not be in the output.
+//The count and the sums and averages of every numeric column are computed alongside the projected ones for the filter to use.
+fn synthesize_having(t:usize,keys:&[Expr],mut aggs:Vec<Gc>,cols:Vec<Expr>,schema:&TestCaseSchema,examples:&[Example],caches:&mut [EvalCache])->Option<Tier1Table> {
+    let table = &examples[0].inputs[t];
+    let width = table.columns.len();
+    let given = aggs.len();
+    if let Some(c) = (0..width).find(|c|!(0..table.rows).any(|r|value_is_null(table,*c,r))) {aggs.push((Agg::Count,c));}
+    for c in (0..width).filter(|c|is_numeric(&table.columns[*c])) {
+        for ag in [Agg::Sum,Agg::Avg] {
+            if !aggs.contains(&(ag.clone(),c)) {aggs.push((ag,c));}
+        }
+    }
+    let source = Box::new(Tier3Table::N(Named(t)));
+    let candidate = Tier2Table::project(Tier3Table::N(Group(source.clone(),keys.to_owned(),aggs.clone())),cols).totop();
+    //columns neither grouped nor aggregated hold an arbitrary row of their group
+    let bare = |prim:&Prim|{
+        let found = Cell::new(false);
+        prim.remap(&|c|{if c<width && !keys.contains(&Expr::Col(c)) {found.set(true);} c});
+        found.get()
+    };
+    let first_extra = width+keys.iter().filter(|x|x.column().is_none()).count()+aggs[..given].iter().filter(|(ag,_)|!ag.in_place()).count();
+    for filtered in synthesize_filter(&candidate,examples,caches,&schema.tolerance) {
+        let (pred,cols) = match &filtered {
+            Tier1Table::N(Tier2Table::Project(Tier3Table::Select(_,pred),cols)) if !pred.iter().any(bare)=>(pred,cols),
+            _=>continue
+        };
+        //only the extra aggregates the condition uses are kept
+        let used = RefCell::new(Vec::new());
+        let record = |c:Cname|{used.borrow_mut().push(c);c};
+        for prim in pred.iter() {prim.remap(&record);}
+        for (x,_) in cols.iter() {x.remap(&record);}
+        let used = used.into_inner();
+        let extras:Vec<usize> = (given..aggs.len()).filter(|k|used.contains(&(first_extra+k-given))).collect();
+        let shift = |c:Cname|if c<first_extra {c} else {first_extra+extras.iter().position(|k|*k==given+c-first_extra).unwrap()};
+        let kept:Vec<Gc> = aggs[..given].iter().chain(extras.iter().map(|k|&aggs[*k])).cloned().collect();
+        let pred:Pred = pred.iter().map(|x|x.remap(&shift)).collect();
+        let cols:Vec<Proj> = cols.iter().map(|(x,alias)|(x.remap(&shift),alias.clone())).collect();
+        let pruned = Tier2Table::Project(Tier3Table::Select(Group(source.clone(),keys.to_owned(),kept),pred),cols).totop();
+        if let Some(found) = finish_candidate(&pruned,schema,examples,caches) {return Some(found);}
+    }
+    None
 }
 
 
@@ -690,91 +3079,465 @@ fn fit_examples(_schema:&TestCaseSchema,examples: &Vec<Example>)->Tier1Table {
 
 
 
+//When synthesis fails, this works out whether the example itself is unreachable with the DSL:
+//output values that exist nowhere, rows no join chain can assemble, and what kind of operator seems to be missing.
+struct Diagnosis {
+    unsourced:Vec<(usize,Vec<usize>)>,//output columns no single input column explains, with the rows whose value appears in no input column
+    unproducible:Vec<usize>,//output rows no join chain contains
+    hints:Vec<String>
+}
+fn value_is_null(a:&Table,ac:usize,ai:usize)->bool {
+    with_column!(&*a.columns[ac],v=>v[ai].is_none())
+}
+fn value_in_column(a:&Table,ac:usize,ai:usize,b:&Table,bc:usize,tol:&Tolerance)->bool {
+    columns_same_type(&a.columns[ac],&b.columns[bc]).is_some() &&
+        (0..b.rows).any(|bi|compare_table_values_tol(a,ac,ai,b,bc,bi,tol))
+}
+fn row_contained(output:&Table,row:usize,joined:&Table,direct:&[bool],tol:&Tolerance)->bool {
+    (0..joined.rows).any(|x|(0..output.columns.len()).all(|j|!direct[j] || value_is_null(output,j,row) ||
+        (0..joined.columns.len()).any(|c|columns_same_type(&output.columns[j],&joined.columns[c]).is_some() &&
+            compare_table_values_tol(output,j,row,joined,c,x,tol))
+    ))
+}
+const DIAGNOSIS_MAX_CHAINS:usize = 2000;
+const DIAGNOSIS_MAX_ROWS:usize = 20000;
+//evaluates join chains of up to max_depth joins without the pruning BreadthFirstExpand does against the output,
+//stopping at chains that grow too large to be plausible
+fn join_chains(graph:&LinkGraph,inputs:&[Table],max_depth:usize)->Vec<Arc<Table>> {
+    fn extend(graph:&LinkGraph,cache:&mut EvalCache,at:NodeIndex,path:&mut Vec<(usize,LinkGraphEdge)>,visited:&mut Vec<(usize,usize)>,max_depth:usize,out:&mut Vec<Arc<Table>>) {
+        if out.len()>=DIAGNOSIS_MAX_CHAINS {return;}
+        let joined = join_tree(cache.tables,visited[0].0,path).0.evaluate_cached(cache);
+        let rows = joined.rows;
+        out.push(joined);
+        if path.len()>=max_depth {return;}
+        for edge in graph.edges(at) {
+            let (from,to) = (graph[at],graph[edge.target()]);
+            if visited.contains(&(to,edge.weight().dest_col)) || rows*cache.tables[to].rows>DIAGNOSIS_MAX_ROWS {continue;}
+            visited.push((from,edge.weight().source_col));
+            visited.push((to,edge.weight().dest_col));
+            path.push((to,edge.weight().clone()));
+            extend(graph,cache,edge.target(),path,visited,max_depth,out);
+            path.pop();visited.pop();visited.pop();
+        }
+    }
+    let mut cache = EvalCache::new(inputs,EVAL_CACHE_CELLS);
+    let mut out = Vec::new();
+    for start in graph.node_indices() {
+        extend(graph,&mut cache,start,&mut vec![],&mut vec![(graph[start],usize::MAX)],max_depth,&mut out);
+    } out
+}
+fn numeric_values(a:&Column)->Option<Vec<Option<f64>>> {
+    if is_numeric(a) {Some(into_numeric(a.clone()))} else {None}
+}
+fn string_values(a:&Column)->Option<&Vec<Option<Str>>> {
+    match a {Column::String(v)=>Some(v),_=>None}
+}
+fn aggregate_hint(schema:&TestCaseSchema,inputs:&[Table],wanted:&[f64],tol:&Tolerance)->Option<String> {
+    for (t,tab) in inputs.iter().enumerate() {
+        for g in 0..tab.columns.len() {
+            let mut groups:Vec<Vec<usize>> = Vec::new();
+            for row in 0..tab.rows {
+                match groups.iter_mut().find(|x|compare_table_values(tab,g,x[0],tab,g,row)) {
+                    Some(group)=>group.push(row),
+                    None=>groups.push(vec![row])
+                }
+            }
+            let mut results:Vec<f64> = groups.iter().map(|x|x.len() as f64).collect();
+            for column in tab.columns.iter().filter_map(|x|numeric_values(x)) {
+                for group in groups.iter() {
+                    let vals:Vec<f64> = group.iter().filter_map(|x|column[*x]).collect();
+                    results.push(vals.iter().sum());
+                    if !vals.is_empty() {results.push(vals.iter().sum::<f64>()/vals.len() as f64);}
+                }
+            }
+            if wanted.iter().all(|w|results.iter().any(|r|tol.numeric_eq(*r,*w))) {
+                let tschema = &schema.inputs[t];
+                return Some(format!("values look like COUNT/SUM/AVG over {} grouped by {}, but no grouping of a single table also produces the other columns",
+                    tschema.name,tschema.columns[g].0));
+            }
+        }
+    }
+    None
+}
+fn string_hint(schema:&TestCaseSchema,inputs:&[Table],wanted:&[String])->Option<String> {
+    for (t,tab) in inputs.iter().enumerate() {
+        for (c,column) in tab.columns.iter().enumerate().filter_map(|(c,x)|string_values(x).map(|v|(c,v))) {
+            if wanted.iter().all(|w|column.iter().flatten().any(|s|s.eq_ignore_ascii_case(w) || s.trim()==w || (s.len()>1 && w.contains(s.as_str())) || (w.len()>1 && s.contains(w.as_str())))) {
+                let tschema = &schema.inputs[t];
+                return Some(format!("values look derived from {}.{} by case changes, trimming, substrings or concatenation, but not by any string expression synthesis tries",
+                    tschema.name,tschema.columns[c].0));
+            }
+        }
+    }
+    None
+}
+fn diagnose(schema:&TestCaseSchema,example:&Example)->Diagnosis {
+    let (inputs,output,tol) = (&example.inputs,&example.output,&schema.tolerance);
+    let targets = all_potential_mappings(inputs,output,tol);
+    let mut diagnosis = Diagnosis {unsourced:Vec::new(),unproducible:Vec::new(),hints:Vec::new()};
+    for (j,options) in targets.iter().enumerate() {
+        if !options.is_empty() {continue;}
+        let missing:Vec<usize> = (0..output.rows).filter(|r|!value_is_null(output,j,*r) &&
+            !inputs.iter().any(|tab|(0..tab.columns.len()).any(|c|value_in_column(output,j,*r,tab,c,tol)))
+        ).collect();
+        let name = &schema.output[j].0;
+        let hint = match &*output.columns[j] {
+            Column::Boolean(_)=>None,
+            column if is_numeric(column)=>{
+                let wanted:Vec<f64> = into_numeric(column.clone()).into_iter().flatten().collect();
+                aggregate_hint(schema,inputs,&wanted,tol)
+            }
+            Column::String(v)=>string_hint(schema,inputs,&v.iter().flatten().map(|x|x.to_string()).collect::<Vec<_>>()),
+            _=>Some(String::from("values look computed from dates, but not by any date expression synthesis tries"))
+        }.unwrap_or_else(||if !missing.is_empty() {
+            String::from("some values appear nowhere in the inputs; check the example")
+        } else {
+            String::from("every value exists somewhere, but no single input column holds them all; rows seem to come from different sources")
+        });
+        diagnosis.hints.push(format!("{}: {}",name,hint));
+        diagnosis.unsourced.push((j,missing));
+    }
+    let joins = join_chains(&extract_comparisons(inputs),inputs,SYNTH_MAX_JOINS);
+    let direct:Vec<bool> = targets.iter().map(|x|x.iter().any(|(_,_,expr)|expr.column().is_some())).collect();//computed values are not looked for in the joins
+    diagnosis.unproducible = (0..output.rows).filter(|r|!joins.iter().any(|j|row_contained(output,*r,j,&direct,tol))).collect();
+    if diagnosis.unsourced.is_empty() && !diagnosis.unproducible.is_empty() {
+        diagnosis.hints.push(String::from("some rows combine values that no chain of equality joins connects"));
+    }
+    if diagnosis.unsourced.is_empty() && diagnosis.unproducible.is_empty() {
+        diagnosis.hints.push(String::from("every output row appears in some join of the inputs; the extra rows or duplicates need a filter, DISTINCT or aggregation"));
+    }
+    diagnosis
+}
+fn print_diagnosis(schema:&TestCaseSchema,example:&Example,diagnosis:&Diagnosis) {
+    println!("diagnosis for {}",example.basepath);
+    for (j,missing) in diagnosis.unsourced.iter() {
+        println!("\toutput column {} has no source column",schema.output[*j].0);
+        for r in missing {
+            println!("\t\trow {}: {} appears in no input",r,cell_string(&example.output.columns[*j],*r,&schema.output[*j].1));
+        }
+    }
+    if !diagnosis.unproducible.is_empty() {
+        println!("\trows no join path can produce: {:?}",diagnosis.unproducible);
+    }
+    for hint in diagnosis.hints.iter() {
+        println!("\thint: {}",hint);
+    }
+}
 
-fn test_fit(schema:&TestCaseSchema,examples: &Vec<Example>,expr:&Tier1Table) {
-    for example in examples.iter() {
-        let comparison:Table = expr.evaluate(&example.inputs);
-        if !compare_table_values_full(&comparison,&example.output) {
-            let comparefile = format!("{}actual.csv",example.basepath);
-            write_file(comparefile.clone(),&schema.output,&comparison);
-            panic!("fit wasn't valid for example. Saved actual result to {}",comparefile);
+
+
+
+
+
+
+//Rows of the expected output are matched against rows of the actual output first exactly (up to tolerance),
+//then leftover rows are paired up when they agree on at least half their cells.
+#[derive(Debug, Serialize)]
+struct ChangedRow {
+    expected_row:usize,
+    actual_row:usize,
+    cells:Vec<(String,String,String)>//column, expected, actual
+}
+#[derive(Debug, Serialize)]
+struct TableDiff {
+    columns:Vec<String>,
+    schema:Vec<String>,//differences in column count or types, when there are any the rows aren't compared
+    missing:Vec<(usize,Vec<String>)>,//expected rows absent from the actual output
+    extra:Vec<(usize,Vec<String>)>,//actual rows absent from the expected output
+    changed:Vec<ChangedRow>,
+    reordered:bool//only the order of the rows differs
+}
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReportFormat {
+    Html,
+    Json
+}
+fn row_strings(table:&Table,row:usize,schema:&[(String,ColumnSchema)])->Vec<String> {
+    table.columns.iter().zip(schema.iter()).map(|(x,(_,sch))|cell_string(x,row,sch)).collect()
+}
+fn schema_differences(actual:&Table,expected:&Table,schema:&[(String,ColumnSchema)])->Vec<String> {
+    if actual.columns.len()!=expected.columns.len() {
+        return vec![format!("the actual output has {} columns, {} expected",actual.columns.len(),expected.columns.len())];
+    }
+    actual.columns.iter().zip(expected.columns.iter()).zip(schema.iter())
+        .filter(|((a,e),_)|columns_same_type(a,e).is_none())
+        .map(|((a,e),(name,_))|format!("{} is {} in the actual output, {} expected",name,a.type_name(),e.type_name())).collect()
+}
+fn diff_tables(actual:&Table,expected:&Table,schema:&[(String,ColumnSchema)],tol:&Tolerance)->TableDiff {
+    let columns:Vec<String> = schema.iter().map(|(x,_)|x.clone()).collect();
+    let mut diff = TableDiff {columns,schema:schema_differences(actual,expected,schema),missing:Vec::new(),extra:Vec::new(),changed:Vec::new(),reordered:false};
+    if !diff.schema.is_empty() {return diff;}
+    let mut used = vec![false;actual.rows];
+    let mut matched = Vec::new();
+    let mut unmatched = Vec::new();
+    for e in 0..expected.rows {
+        match (0..actual.rows).find(|a|!used[*a] && compare_rows(actual,*a,expected,e,tol)==Ordering::Equal) {
+            Some(a)=>{used[a]=true;matched.push(a);}
+            None=>unmatched.push(e)
         }
     }
+    for e in unmatched {
+        let agreeing = |a:usize|(0..expected.columns.len()).filter(|c|compare_table_values_tol(actual,*c,a,expected,*c,e,tol)).count();
+        match (0..actual.rows).filter(|a|!used[*a]).max_by_key(|a|(agreeing(*a),std::cmp::Reverse(*a))) {
+            Some(a) if agreeing(a)*2>=expected.columns.len() => {
+                used[a]=true;
+                let cells = (0..expected.columns.len()).filter(|c|!compare_table_values_tol(actual,*c,a,expected,*c,e,tol)).map(|c|(
+                    schema[c].0.clone(),cell_string(&expected.columns[c],e,&schema[c].1),cell_string(&actual.columns[c],a,&schema[c].1)
+                )).collect();
+                diff.changed.push(ChangedRow {expected_row:e,actual_row:a,cells});
+            }
+            _=>diff.missing.push((e,row_strings(expected,e,schema)))
+        }
+    }
+    diff.extra = (0..actual.rows).filter(|a|!used[*a]).map(|a|(a,row_strings(actual,a,schema))).collect();
+    diff.reordered = diff.missing.is_empty() && diff.extra.is_empty() && diff.changed.is_empty() && matched.windows(2).any(|x|x[0]>x[1]);
+    diff
+}
+fn print_diff(diff:&TableDiff) {
+    const RED:&str = "\x1b[31m";
+    const GREEN:&str = "\x1b[32m";
+    const YELLOW:&str = "\x1b[33m";
+    const RESET:&str = "\x1b[0m";
+    for difference in diff.schema.iter() {
+        println!("{}!\t{}{}",RED,difference,RESET);
+    }
+    println!("\t{}",diff.columns.join(", "));
+    for (r,cells) in diff.missing.iter() {
+        println!("{}-\texpected row {}: {}{}",RED,r,cells.join(", "),RESET);
+    }
+    for (r,cells) in diff.extra.iter() {
+        println!("{}+\tactual row {}: {}{}",GREEN,r,cells.join(", "),RESET);
+    }
+    for changed in diff.changed.iter() {
+        println!("{}~\texpected row {} / actual row {}{}",YELLOW,changed.expected_row,changed.actual_row,RESET);
+        for (col,expected,actual) in changed.cells.iter() {
+            println!("\t\t{}: {}{}{} -> {}{}{}",col,RED,expected,RESET,GREEN,actual,RESET);
+        }
+    }
+    if diff.reordered {
+        println!("{}\tsame rows, different order{}",YELLOW,RESET);
+    }
+}
+fn html_escape(a:&str)->String {
+    a.replace('&',"&amp;").replace('<',"&lt;").replace('>',"&gt;")
+}
+fn write_diff_report(filepath:String,diff:&TableDiff,format:ReportFormat) {
+    let contents = match format {
+        ReportFormat::Json=>serde_json::to_string_pretty(diff).expect("Could not serialize diff"),
+        ReportFormat::Html=>{
+            let header:String = diff.columns.iter().map(|x|format!("<th>{}</th>",html_escape(x))).collect();
+            let mut rows = String::new();
+            let cells = |x:&Vec<String>|x.iter().map(|y|format!("<td>{}</td>",html_escape(y))).collect::<String>();
+            for (r,x) in diff.missing.iter() {rows+=&format!("<tr class=\"missing\"><td>-{}</td>{}</tr>\n",r,cells(x));}
+            for (r,x) in diff.extra.iter() {rows+=&format!("<tr class=\"extra\"><td>+{}</td>{}</tr>\n",r,cells(x));}
+            for changed in diff.changed.iter() {
+                let row:String = diff.columns.iter().map(|col|match changed.cells.iter().find(|(c,_,_)|c==col) {
+                    Some((_,e,a))=>format!("<td class=\"changed\">{} &rarr; {}</td>",html_escape(e),html_escape(a)),
+                    None=>String::from("<td></td>")
+                }).collect();
+                rows+=&format!("<tr><td>~{}/{}</td>{}</tr>\n",changed.expected_row,changed.actual_row,row);
+            }
+            let schema:String = diff.schema.iter().map(|x|format!("<p class=\"missing\">{}</p>\n",html_escape(x))).collect();
+            format!("<html><head><style>.missing{{background:#fdd}} .extra{{background:#dfd}} .changed{{background:#ffd}}</style></head><body>\n{}{}<table border=\"1\">\n<tr><th></th>{}</tr>\n{}</table></body></html>\n",
+                schema,if diff.reordered {"<p>same rows, different order</p>\n"} else {""},header,rows)
+        }
+    };
+    fs::write(&filepath,contents).unwrap_or_else(|_|panic!("Could not write report to {}",filepath));
 }
-fn read_table(filepath:String,schema:&Vec<(String,ColumnSchema)>)->Table {
-    let file = File::open(filepath.clone()).expect(format!("File not found: {}",filepath).as_str());
-    let (cols,fmt) : (Vec<_>,Vec<_>) = schema.iter().map(|(_,u)|match u{
+//on a mismatch, saves the actual result next to the example and shows how it differs
+fn check_example(schema:&TestCaseSchema,example:&Example,expr:&Tier1Table,report:Option<ReportFormat>)->bool {
+    let comparison:Table = expr.evaluate(&example.inputs);
+    if compare_tables(&comparison,&example.output,schema.comparison,&schema.tolerance) {return true;}
+    let comparefile = format!("{}actual.csv",example.basepath);
+    write_file(comparefile.clone(),&schema.output,&comparison);
+    let diff = diff_tables(&comparison,&example.output,&schema.output,&schema.tolerance);
+    print_diff(&diff);
+    if let Some(format) = report {
+        write_diff_report(format!("{}diff.{}",example.basepath,if format==ReportFormat::Html {"html"} else {"json"}),&diff,format);
+    }
+    println!("the program doesn't fit {}, saved its result to {}",example.basepath,comparefile);
+    false
+}
+fn test_fit(schema:&TestCaseSchema,examples: &[Example],expr:&Tier1Table,report:Option<ReportFormat>) {
+    for example in examples.iter() {
+        if !check_example(schema,example,expr,report) {panic!("fit wasn't valid for example");}
+    }
+}
+fn read_table(filepath:String,schema:&[(String,ColumnSchema)],dictionary:&mut Dictionary)->Table {
+    let file = File::open(filepath.clone()).unwrap_or_else(|_|panic!("File not found: {}",filepath));
+    let (mut cols,fmt) : (Vec<_>,Vec<_>) = schema.iter().map(|(_,u)|match u{
         ColumnSchema::String=>(Column::String(vec![]),None),
         ColumnSchema::Numeric=>(Column::Numeric(vec![]),None),
+        ColumnSchema::Integer=>(Column::Integer(vec![]),None),
+        ColumnSchema::Boolean=>(Column::Boolean(vec![]),None),
+        ColumnSchema::Decimal=>(Column::Decimal(vec![]),None),
         ColumnSchema::Time(fmt)=>(Column::Time(vec![]),Some(fmt))
     }).unzip();
-    let mut tab = Table {columns: cols,rows:0};
+    let mut rows = 0;
     for result in Reader::from_reader(file).records() {
-        tab.rows+=1;
+        rows+=1;
         for (ind,rec) in result.unwrap().iter().enumerate() {
             let trimrec = rec.trim();
-            match &mut tab.columns[ind] {
+            match &mut cols[ind] {
                 Column::String(v)=>{
-                    v.push(if trimrec.len()==0 {None} else {Some(String::from(trimrec))})
+                    v.push(if trimrec.is_empty() {None} else {Some(dictionary.intern(trimrec))})
                 }
                 Column::Numeric(v)=>{
-                    v.push(if trimrec.len()==0 {None} else {Some(f64::from_str(trimrec).expect("CSV column doesn't have proper numeric type"))})
+                    v.push(if trimrec.is_empty() {None} else {Some(f64::from_str(trimrec).expect("CSV column doesn't have proper numeric type"))})
                 }
                 Column::Time(v)=>{
-                    v.push(if trimrec.len()==0 {None} else {Some(NaiveDateTime::parse_from_str("23:56:04", fmt[ind].unwrap()).expect("CSV column doens't have proper time format"))})
+                    v.push(if trimrec.is_empty() {None} else {Some(NaiveDateTime::parse_from_str(trimrec, fmt[ind].unwrap()).expect("CSV column doens't have proper time format"))})
+                }
+                Column::Integer(v)=>{
+                    v.push(if trimrec.is_empty() {None} else {Some(i64::from_str(trimrec).expect("CSV column doesn't have proper integer type"))})
+                }
+                Column::Boolean(v)=>{
+                    v.push(if trimrec.is_empty() {None} else {Some(parse_bool(trimrec).expect("CSV column doesn't have proper boolean type"))})
+                }
+                Column::Decimal(v)=>{
+                    v.push(if trimrec.is_empty() {None} else {Some(Decimal::parse(trimrec).expect("CSV column doesn't have proper decimal type"))})
                 }
             }
         }
-    } tab
+    }
+    Table {columns:cols.into_iter().map(LazyColumn::from).collect(),rows}
+}
+fn cell_string(column:&Column,i:usize,schema:&ColumnSchema)->String {
+    match column {
+        Column::Numeric(v)=>v[i].map(|y|format!("{}",y)).unwrap_or(String::from("")),
+        Column::Integer(v)=>v[i].map(|y|format!("{}",y)).unwrap_or(String::from("")),
+        Column::Boolean(v)=>v[i].map(|y|String::from(if y {"true"} else {"false"})).unwrap_or(String::from("")),
+        Column::Decimal(v)=>v[i].map(|y|format!("{}",y)).unwrap_or(String::from("")),
+        Column::String(v)=>v[i].as_ref().map(|y|y.to_string()).unwrap_or(String::from("")),
+        Column::Time(v)=>v[i].map(|y|format!("{}",y.format(match schema {
+            ColumnSchema::Time(fmt)=>fmt,
+            _=>"%Y-%m-%d %H:%M:%S"
+        }))).unwrap_or(String::from(""))
+    }
 }
-fn write_file(filepath:String,schema:&Vec<(String,ColumnSchema)>,table:&Table) {
+//columns past the end of the schema are written with default formats, under their position
+fn write_file(filepath:String,schema:&[(String,ColumnSchema)],table:&Table) {
     let mut wtr = Writer::from_path(filepath).expect("Could not write to output file");
+    let schema:Vec<(String,ColumnSchema)> = (0..table.columns.len()).map(|c|schema.get(c).cloned().unwrap_or((format!("column{}",c+1),ColumnSchema::String))).collect();
+    wtr.write_record(schema.iter().map(|(x,_)|x)).expect("Could not write header to file");
     for i in 0..table.rows {
-        let row:Vec<String> = table.columns.iter().map(|x|match x {
-            Column::Numeric(v)=>v[i].map(|y|format!("{}",y)).unwrap_or(String::from("")),
-            Column::String(v)=>v[i].clone().unwrap_or(String::from("")),
-            Column::Time(v)=>v[i].map(|y|format!("{}",y.format(match &schema[i].1 {
-                ColumnSchema::Time(fmt)=>&fmt,
-                _=>"%Y-%m-%d %H:%M:%S"
-            }))).unwrap_or(String::from(""))
-        }).collect();
+        let row:Vec<String> = table.columns.iter().zip(schema.iter()).map(|(x,(_,sch))|cell_string(x,i,sch)).collect();
         wtr.write_record(&row).expect("Could not write record to file");
     }
     wtr.flush().expect("Could not flush to output file");
 }
+fn print_provenance(schema:&TestCaseSchema,example:&Example,expr:&Tier1Table,row:Option<usize>) {
+    let (result,lineage) = expr.evaluate_with_provenance(&example.inputs);
+    println!("provenance for {}",example.basepath);
+    for r in (0..result.rows).filter(|r|row.map(|x|x==*r).unwrap_or(true)) {
+        println!("\trow {}: {}",r,row_strings(&result,r,&schema.output).join(", "));
+        for (t,i) in lineage[r].iter() {
+            println!("\t\t{} row {}: {}",schema.inputs[*t].name,i,row_strings(&example.inputs[*t],*i,&schema.inputs[*t].columns).join(", "));
+        }
+    }
+    if let Some(r) = row {
+        if r>=result.rows {println!("\tthe result only has {} rows",result.rows);}
+    }
+}
+//every flag takes a value; anything else is the testcase
+const FLAGS:[&str;4] = ["--report","--workers","--provenance","--holdout"];
+fn usage_error(message:String)->! {
+    eprintln!("{}",message);
+    std::process::exit(2)
+}
+struct Args {
+    testcase:Option<String>,
+    flags:HashMap<String,String>
+}
+fn parse_args(mut args:impl Iterator<Item=String>)->Args {
+    let mut res = Args {testcase:None,flags:HashMap::new()};
+    while let Some(arg) = args.next() {
+        if FLAGS.contains(&arg.as_str()) {
+            let value = args.next().unwrap_or_else(||usage_error(format!("{} needs a value",arg)));
+            res.flags.insert(arg,value);
+        } else if arg.starts_with("--") {
+            usage_error(format!("unknown flag {}, expected one of {}",arg,FLAGS.join(", ")));
+        } else if res.testcase.is_some() {
+            usage_error(String::from("only one test case can be attempted at a time"));
+        } else {
+            res.testcase = Some(arg);
+        }
+    } res
+}
 fn main() {
-    match env::args_os().nth(1) {
+    let args = parse_args(env::args().skip(1));
+    let flag_value = |name:&str|args.flags.get(name).cloned();
+    let report = flag_value("--report").map(|x|match x.as_str() {
+        "html"=>ReportFormat::Html,
+        "json"=>ReportFormat::Json,
+        _=>usage_error(String::from("--report must be html or json"))
+    });
+    //only the join and window searches are split across workers; grouping, reshaping and set operations run on one
+    let workers = flag_value("--workers").map_or_else(||thread::available_parallelism().map_or(1,|x|x.get()),|x|{
+        usize::from_str(&x).ok().filter(|x|*x>0).unwrap_or_else(||usage_error(String::from("--workers takes a positive number")))
+    });
+    //an example left out of the search, to check the program found against
+    let holdout = flag_value("--holdout");
+    let provenance = flag_value("--provenance").map(|x|if x=="all" {None} else {
+        Some(usize::from_str(&x).unwrap_or_else(|_|usage_error(String::from("--provenance takes a row number or all"))))
+    });
+    match args.testcase {
         None => {
             println!("please specify which test case should be attempted.");
             println!("available test cases:");
             for path in fs::read_dir("./testcases_v1/").unwrap() {
                 let unwr = path.unwrap();
                 if unwr.metadata().unwrap().file_type().is_dir() {
-                    println!("\t{}",unwr.path().components().last().unwrap().as_os_str().to_str().unwrap());
+                    println!("\t{}",unwr.path().components().next_back().unwrap().as_os_str().to_str().unwrap());
                 }
             }
         },
-        Some(file_path) => {
-            let patstr = file_path.to_str().unwrap();
+        Some(patstr) => {
             match fs::read_dir(format!("./testcases_v1/{}",patstr)) {
                 Ok(iter)=>{
                     let mut fileschema = File::open(format!("./testcases_v1/{}/schema.json",patstr)).unwrap();
                     let mut dataschema = String::new();
                     fileschema.read_to_string(&mut dataschema).unwrap();
                     let schema:TestCaseSchema = serde_json::from_str(&dataschema).expect("JSON was not well-formatted");
-                    let examples:Vec<Example> = iter.filter_map(|x|{
+                    let (held,examples):(Vec<Example>,Vec<Example>) = iter.filter_map(|x|{
                         let y=x.unwrap();
                         if !y.metadata().unwrap().file_type().is_dir() {return None}
                         let ypath = y.path();
-                        let ycomp = ypath.components().last().unwrap().as_os_str().to_str().unwrap();
-                        return Some(Example {
-                            inputs: schema.inputs.iter().map(|sch|read_table(format!("./testcases_v1/{}/{}/input_tables/{}.csv",patstr,ycomp,sch.name),&sch.columns)).collect(),
-                            output: read_table(format!("./testcases_v1/{}/{}/output_table.csv",patstr,ycomp),&schema.output),
+                        let ycomp = ypath.components().next_back().unwrap().as_os_str().to_str().unwrap();
+                        let mut dictionary = Dictionary::new();
+                        Some(Example {
+                            inputs: schema.inputs.iter().map(|sch|read_table(format!("./testcases_v1/{}/{}/input_tables/{}.csv",patstr,ycomp,sch.name),&sch.columns,&mut dictionary)).collect(),
+                            output: read_table(format!("./testcases_v1/{}/{}/output_table.csv",patstr,ycomp),&schema.output,&mut dictionary),
                             basepath: format!("./testcases_v1/{}/{}/",patstr,ycomp)
                         })
-                    }).collect();
-                    let fit = fit_examples(&schema,&examples);
-                    test_fit(&schema,&examples,&fit);
+                    }).partition(|x|holdout.as_ref().is_some_and(|name|x.basepath==format!("./testcases_v1/{}/{}/",patstr,name)));
+                    if let Some(name) = holdout.as_ref() {
+                        if held.is_empty() {usage_error(format!("{} has no example {} to hold out",patstr,name));}
+                        if examples.is_empty() {usage_error(String::from("--holdout needs another example to search with"));}
+                    }
+                    match fit_examples(&schema,&examples,workers) {
+                        Some(fit)=>{
+                            println!("{:?}",fit);
+                            match program_sql(&fit,&schema) {
+                                Ok(sql)=>println!("{}",sql),
+                                Err(e)=>println!("no SQL: {}",e)
+                            }
+                            //lineage is shown first, as it helps most when the program turns out not to fit
+                            if let Some(row) = provenance {
+                                for example in examples.iter() {print_provenance(&schema,example,&fit,row);}
+                            }
+                            test_fit(&schema,&examples,&fit,report);
+                            for example in held.iter() {
+                                if check_example(&schema,example,&fit,report) {println!("the program also fits {}",example.basepath);}
+                            }
+                        }
+                        None=>{
+                            println!("no program found");
+                            for example in examples.iter() {
+                                print_diagnosis(&schema,example,&diagnose(&schema,example));
+                            }
+                        }
+                    }
                 },
                 Err(_)=>{
                     println!("cannot read testcase directory")
@@ -784,3 +3547,278 @@ fn main() {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    type Rows = Vec<Vec<Option<i64>>>;
+    type Case<'a> = (&'a str,&'a [&'a [Option<i64>]],Rows);
+    fn table(rows:&[&[Option<i64>]],width:usize)->Table {
+        let columns = (0..width).map(|c|Column::Integer(rows.iter().map(|r|r[c]).collect()).into()).collect();
+        Table {columns,rows:rows.len()}
+    }
+    fn rows(t:&Table)->Rows {
+        (0..t.rows).map(|r|t.columns.iter().map(|c|match &**c {
+            Column::Integer(v)=>v[r],
+            Column::Decimal(v)=>v[r].map(|d|{assert_eq!(d.scale,0);d.units as i64}),
+            other=>panic!("expected Integer, got {}",other.type_name())
+        }).collect()).collect()
+    }
+    fn sorted(mut a:Rows)->Rows {a.sort();a}
+    fn top(t:Tier4Table)->Tier1Table {Tier1Table::N(Tier2Table::N(Tier3Table::N(t)))}
+
+    #[test]
+    fn not_in_with_null() {
+        let cases:[Case;3] = [
+            ("list holds NULL",&[&[Some(1)],&[None]],vec![]),
+            ("no NULL in list",&[&[Some(1)]],vec![vec![Some(2)]]),
+            ("empty list",&[],vec![vec![Some(1)],vec![Some(2)],vec![None]])
+        ];
+        for (name,list,expected) in cases {
+            let tables = [table(&[&[Some(1)],&[Some(2)],&[None]],1),table(list,1)];
+            let program = Tier1Table::N(Tier2Table::N(Tier3Table::Select(Named(0),vec![Prim::NotIn(Expr::Col(0),Box::new(top(Named(1))),vec![])])));
+            assert_eq!(rows(&program.evaluate(&tables)),expected,"{}",name);
+        }
+    }
+
+    #[test]
+    fn null_join_keys() {
+        let left:&[&[Option<i64>]] = &[&[Some(1),Some(10)],&[None,Some(20)]];
+        let right:&[&[Option<i64>]] = &[&[Some(1),Some(100)],&[None,Some(200)]];
+        let cases = [
+            ("inner",top(Join(Box::new(Named(0)),Box::new(Named(1)),vec![(0,0)])),vec![vec![Some(1),Some(10),Some(1),Some(100)]]),
+            ("left",top(LeftJoin(Box::new(Named(0)),Box::new(Tier3Table::N(Named(1))),(0,0))),vec![
+                vec![Some(1),Some(10),Some(1),Some(100)],
+                vec![None,Some(20),None,None]
+            ])
+        ];
+        for (name,program,expected) in cases {
+            let tables = [table(left,2),table(right,2)];
+            assert_eq!(sorted(rows(&program.evaluate(&tables))),sorted(expected),"{}",name);
+        }
+    }
+
+    #[test]
+    fn null_is_its_own_group() {
+        let input:&[&[Option<i64>]] = &[&[Some(1),Some(5)],&[None,Some(6)],&[Some(1),Some(7)],&[None,Some(8)],&[Some(2),Some(9)]];
+        let cases = [
+            ("count",Agg::Count,vec![vec![None,Some(6),Some(2)],vec![Some(1),Some(5),Some(2)],vec![Some(2),Some(9),Some(1)]]),
+            ("sum",Agg::Sum,vec![vec![None,Some(6),Some(14)],vec![Some(1),Some(5),Some(12)],vec![Some(2),Some(9),Some(9)]])
+        ];
+        for (name,ag,expected) in cases {
+            let tables = [table(input,2)];
+            let program = top(Group(Box::new(Tier3Table::N(Named(0))),vec![Expr::Col(0)],vec![(ag,1)]));
+            assert_eq!(sorted(rows(&program.evaluate(&tables))),expected,"{}",name);
+        }
+    }
+
+    #[test]
+    fn nulls_first_and_last() {
+        let input:&[&[Option<i64>]] = &[&[Some(2)],&[None],&[Some(1)]];
+        let cases = [
+            (Key::Asc(0,Nulls::First),[None,Some(1),Some(2)]),
+            (Key::Asc(0,Nulls::Last),[Some(1),Some(2),None]),
+            (Key::Desc(0,Nulls::First),[None,Some(2),Some(1)]),
+            (Key::Desc(0,Nulls::Last),[Some(2),Some(1),None])
+        ];
+        for (key,expected) in cases {
+            let tables = [table(input,1)];
+            let program = Tier1Table::Order(Tier2Table::N(Tier3Table::N(Named(0))),vec![key.clone()]);
+            let expected:Rows = expected.iter().map(|x|vec![*x]).collect();
+            assert_eq!(rows(&program.evaluate(&tables)),expected,"{:?}",key);
+        }
+    }
+
+    #[test]
+    fn set_lineage_is_deduplicated() {
+        //both joined rows come from the one row of table 1, and INTERSECT merges them
+        let tables = [table(&[&[Some(1)],&[Some(1)]],1),table(&[&[Some(1)]],1)];
+        let joined = Tier2Table::Project(Tier3Table::N(Join(Box::new(Named(0)),Box::new(Named(1)),vec![(0,0)])),vec![(Expr::Col(1),None)]);
+        let program = Tier1Table::Set(SetOp::Intersect,false,Box::new(Tier1Table::N(joined)),Box::new(top(Named(1))));
+        let (result,lineage) = program.evaluate_with_provenance(&tables);
+        assert_eq!(result.rows,1);
+        assert_eq!(lineage,vec![vec![(0,0),(0,1),(1,0)]]);
+    }
+
+    #[test]
+    fn decimal_overflow() {
+        let (one,tiny,huge) = (Decimal {units:1,scale:0},Decimal {units:1,scale:60},Decimal {units:i128::MAX,scale:0});
+        assert_eq!(one.partial_cmp(&tiny),Some(Ordering::Greater));
+        assert_eq!(huge.add(one),None);
+        assert_eq!(one.add(tiny),None);
+        let column = Column::Decimal(vec![Some(huge),Some(one),Some(one)]);
+        match sum_rows(&column,&[&[0,1],&[1,2]]) {
+            Column::Decimal(v)=>assert_eq!(v,vec![None,Some(Decimal {units:2,scale:0})]),
+            other=>panic!("expected Decimal, got {}",other.type_name())
+        }
+    }
+
+    #[test]
+    fn ungrouped_columns_in_sql() {
+        let schema = TestCaseSchema {
+            inputs:vec![TableSchema {name:String::from("t"),columns:["a","b","c"].iter().map(|x|(String::from(*x),ColumnSchema::Integer)).collect()}],
+            output:vec![],comparison:Comparison::default(),tolerance:Tolerance::default()
+        };
+        let grouped = Group(Box::new(Tier3Table::N(Named(0))),vec![Expr::Col(0)],vec![(Agg::Max,1)]);
+        let project = |e:Expr|Tier1Table::N(Tier2Table::Project(Tier3Table::N(grouped.clone()),vec![(e,None)]));
+        let cases = [
+            ("grouped",project(Expr::Col(0)),true),
+            ("aggregated",project(Expr::Arith(Arith::Add,Box::new(Expr::Col(1)),Box::new(Expr::Num(Num(2.0))))),true),
+            ("neither",project(Expr::Arith(Arith::Add,Box::new(Expr::Col(2)),Box::new(Expr::Num(Num(2.0))))),false),
+            ("only dropped",top(grouped.clone()),true)
+        ];
+        for (name,program,valid) in cases {
+            let sql = program_sql(&program,&schema);
+            assert_eq!(sql.is_ok(),valid,"{}: {:?}",name,sql);
+        }
+    }
+
+    #[test]
+    fn diff_categories() {
+        let schema:Vec<(String,ColumnSchema)> = ["id","v"].iter().map(|x|(String::from(*x),ColumnSchema::Integer)).collect();
+        let expected:&[&[Option<i64>]] = &[&[Some(1),Some(10)],&[Some(2),Some(20)],&[Some(3),Some(30)]];
+        //missing and extra rows by number, changed rows as (expected, actual, changed columns), and whether only the order differs
+        type Summary = (Vec<usize>,Vec<usize>,Vec<(usize,usize,Vec<String>)>,bool);
+        type DiffCase<'a> = (&'a str,&'a [&'a [Option<i64>]],Summary);
+        let cases:[DiffCase;5] = [
+            ("same",&[&[Some(1),Some(10)],&[Some(2),Some(20)],&[Some(3),Some(30)]],(vec![],vec![],vec![],false)),
+            ("missing",&[&[Some(1),Some(10)],&[Some(3),Some(30)]],(vec![1],vec![],vec![],false)),
+            ("extra",&[&[Some(1),Some(10)],&[Some(2),Some(20)],&[Some(3),Some(30)],&[Some(9),Some(90)]],(vec![],vec![3],vec![],false)),
+            ("changed",&[&[Some(1),Some(10)],&[Some(2),Some(25)],&[Some(3),Some(30)]],(vec![],vec![],vec![(1,1,vec![String::from("v")])],false)),
+            ("reordered",&[&[Some(3),Some(30)],&[Some(1),Some(10)],&[Some(2),Some(20)]],(vec![],vec![],vec![],true))
+        ];
+        for (name,actual,expected_diff) in cases {
+            let diff = diff_tables(&table(actual,2),&table(expected,2),&schema,&EXACT);
+            assert!(diff.schema.is_empty(),"{}",name);
+            let summary:Summary = (
+                diff.missing.iter().map(|x|x.0).collect(),
+                diff.extra.iter().map(|x|x.0).collect(),
+                diff.changed.iter().map(|x|(x.expected_row,x.actual_row,x.cells.iter().map(|c|c.0.clone()).collect())).collect(),
+                diff.reordered
+            );
+            assert_eq!(summary,expected_diff,"{}",name);
+        }
+        let narrower = diff_tables(&table(&[&[Some(1)]],1),&table(expected,2),&schema,&EXACT);
+        assert_eq!(narrower.schema.len(),1);
+        assert!(narrower.missing.is_empty() && narrower.extra.is_empty());
+    }
+
+    #[test]
+    fn diff_json_report() {
+        let schema:Vec<(String,ColumnSchema)> = ["id","v"].iter().map(|x|(String::from(*x),ColumnSchema::Integer)).collect();
+        let diff = diff_tables(&table(&[&[Some(1),Some(11)],&[Some(4),Some(40)]],2),&table(&[&[Some(1),Some(10)],&[Some(2),Some(20)]],2),&schema,&EXACT);
+        let path = std::env::temp_dir().join(format!("synthesis_diff_{}.json",std::process::id()));
+        write_diff_report(path.to_str().unwrap().to_string(),&diff,ReportFormat::Json);
+        let report:serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(report,serde_json::json!({
+            "columns":["id","v"],
+            "schema":[],
+            "missing":[[1,["2","20"]]],
+            "extra":[[1,["4","40"]]],
+            "changed":[{"expected_row":0,"actual_row":0,"cells":[["v","10","11"]]}],
+            "reordered":false
+        }));
+    }
+
+    #[test]
+    fn tolerant_comparison() {
+        let numeric = |rows:&[&[f64]]|Table {
+            columns:(0..rows[0].len()).map(|c|Column::Numeric(rows.iter().map(|r|Some(r[c])).collect()).into()).collect(),
+            rows:rows.len()
+        };
+        let absolute = |x:f64|Tolerance {absolute:x,..EXACT};
+        type ToleranceCase<'a> = (&'a str,&'a [&'a [f64]],&'a [&'a [f64]],Comparison,Tolerance,bool);//actual, expected
+        let cases:[ToleranceCase;11] = [
+            ("exact, any order",&[&[1.0],&[2.0]],&[&[2.0],&[1.0]],Comparison::Unordered,EXACT,true),
+            ("within tolerance, any order",&[&[1.04],&[1.96]],&[&[2.0],&[1.0]],Comparison::Unordered,absolute(0.05),true),
+            ("outside tolerance",&[&[1.0],&[2.1]],&[&[2.0],&[1.0]],Comparison::Unordered,absolute(0.05),false),
+            ("rows are matched one to one",&[&[1.0],&[1.0]],&[&[1.0],&[1.2]],Comparison::Unordered,absolute(0.05),false),
+            //sorted, the rows pair up as (1.00, 5) with (1.00, 3), though each has a partner within the tolerance
+            ("sorting apart",&[&[1.00,5.0],&[1.01,3.0]],&[&[1.01,5.0],&[1.00,3.0]],Comparison::Unordered,absolute(0.02),true),
+            ("duplicates count",&[&[1.0],&[1.0],&[2.0]],&[&[1.01],&[2.0]],Comparison::Unordered,absolute(0.05),false),
+            ("duplicates don't count for Set",&[&[1.0],&[1.0],&[2.0]],&[&[1.01],&[2.0]],Comparison::Set,absolute(0.05),true),
+            ("relative",&[&[101.0]],&[&[100.0]],Comparison::Unordered,Tolerance {relative:0.02,..EXACT},true),
+            ("relative, too far",&[&[101.0]],&[&[100.0]],Comparison::Unordered,Tolerance {relative:0.005,..EXACT},false),
+            ("in order",&[&[1.01],&[2.0]],&[&[1.0],&[2.0]],Comparison::Ordered,absolute(0.05),true),
+            ("out of order",&[&[2.0],&[1.01]],&[&[1.0],&[2.0]],Comparison::Ordered,absolute(0.05),false)
+        ];
+        for (name,actual,expected,mode,tol,fits) in cases {
+            assert_eq!(compare_tables(&numeric(actual),&numeric(expected),mode,&tol),fits,"{}",name);
+        }
+    }
+
+    #[test]
+    fn set_operations_all_and_distinct() {
+        let left:&[&[Option<i64>]] = &[&[Some(1)],&[Some(1)],&[Some(2)],&[Some(3)],&[None],&[None]];
+        let right:&[&[Option<i64>]] = &[&[Some(1)],&[Some(2)],&[Some(2)],&[None]];
+        let cases = [
+            (SetOp::Union,true,vec![None,None,None,Some(1),Some(1),Some(1),Some(2),Some(2),Some(2),Some(3)]),
+            (SetOp::Union,false,vec![None,Some(1),Some(2),Some(3)]),
+            (SetOp::Intersect,true,vec![None,Some(1),Some(2)]),
+            (SetOp::Intersect,false,vec![None,Some(1),Some(2)]),
+            (SetOp::Except,true,vec![None,Some(1),Some(3)]),
+            (SetOp::Except,false,vec![Some(3)])
+        ];
+        for (op,all,expected) in cases {
+            let tables = [table(left,1),table(right,1)];
+            let program = Tier1Table::Set(op.clone(),all,Box::new(top(Named(0))),Box::new(top(Named(1))));
+            let expected:Rows = expected.into_iter().map(|x|vec![x]).collect();
+            assert_eq!(sorted(rows(&program.evaluate(&tables))),expected,"{:?} all={}",op,all);
+        }
+    }
+
+    #[test]
+    fn window_rank_and_running_sum() {
+        let input:&[&[Option<i64>]] = &[
+            &[Some(1),Some(10),Some(5)],&[Some(1),Some(20),Some(7)],&[Some(1),Some(20),Some(1)],
+            &[Some(2),Some(10),Some(3)],&[Some(2),None,Some(4)]
+        ];
+        type WindowCase<'a> = (&'a str,Vec<Cname>,WindowFn,[Option<i64>;5]);
+        let cases:[WindowCase;4] = [
+            ("rank by partition",vec![0],WindowFn::Rank,[Some(1),Some(2),Some(2),Some(1),Some(2)]),
+            ("rank over everything",vec![],WindowFn::Rank,[Some(1),Some(3),Some(3),Some(1),Some(5)]),
+            ("running sum by partition",vec![0],WindowFn::Sum(2),[Some(5),Some(13),Some(13),Some(3),Some(7)]),
+            ("running sum over everything",vec![],WindowFn::Sum(2),[Some(8),Some(16),Some(16),Some(8),Some(20)])
+        ];
+        for (name,partition,func,appended) in cases {
+            let tables = [table(input,3)];
+            let program = top(Window(Box::new(Tier3Table::N(Named(0))),partition,vec![Key::Asc(1,Nulls::Last)],func));
+            let expected:Rows = input.iter().zip(appended).map(|(r,x)|r.iter().cloned().chain(Some(x)).collect()).collect();
+            assert_eq!(sorted(rows(&program.evaluate(&tables))),sorted(expected),"{}",name);
+        }
+    }
+
+    #[test]
+    fn pivot_and_unpivot() {
+        let input:&[&[Option<i64>]] = &[&[Some(1),Some(10),None],&[Some(2),Some(20),Some(30)]];
+        let tables = [table(input,3)];
+        let program = top(Unpivot(Box::new(Tier3Table::N(Named(0))),vec![(1,String::from("a")),(2,String::from("b"))]));
+        let mut result = program.evaluate(&tables);
+        let labels = match &*result.columns.remove(1) {
+            Column::String(v)=>v.iter().map(|x|x.as_ref().map(|s|s.to_string())).collect::<Vec<_>>(),
+            other=>panic!("expected String labels, got {}",other.type_name())
+        };
+        let got:Vec<_> = labels.into_iter().zip(rows(&result)).collect();
+        let label = |x:&str|Some(String::from(x));
+        assert_eq!(got,vec![
+            (label("a"),vec![Some(1),Some(10)]),(label("b"),vec![Some(1),None]),
+            (label("a"),vec![Some(2),Some(20)]),(label("b"),vec![Some(2),Some(30)])
+        ]);
+
+        let input:&[&[Option<i64>]] = &[
+            &[Some(1),Some(1),Some(10)],&[Some(1),Some(1),Some(15)],&[Some(1),Some(2),Some(5)],
+            &[Some(2),Some(2),Some(7)],&[Some(2),Some(1),None],&[None,Some(1),Some(3)]
+        ];
+        let cases:[(&str,&[&str],Rows);3] = [
+            ("repeated label keeps the largest",&["1"],vec![vec![None,Some(3)],vec![Some(1),Some(15)],vec![Some(2),None]]),
+            ("label with no rows",&["2","3"],vec![vec![None,None,None],vec![Some(1),Some(5),None],vec![Some(2),Some(7),None]]),
+            ("no labels",&[],vec![vec![None],vec![Some(1)],vec![Some(2)]])
+        ];
+        for (name,labels,expected) in cases {
+            let tables = [table(input,3)];
+            let program = top(Pivot(Box::new(Tier3Table::N(Named(0))),1,2,labels.iter().map(|x|x.to_string()).collect()));
+            assert_eq!(sorted(rows(&program.evaluate(&tables))),expected,"{}",name);
+        }
+    }
+}